@@ -4,6 +4,7 @@ extern crate rand;
 extern crate svg;
 extern crate terrain;
 
+use std::env;
 use std::io;
 
 use na::{Point2, Point3};
@@ -11,12 +12,12 @@ use petgraph::stable_graph::StableGraph;
 use rand::prelude::*;
 use rand::rngs::SmallRng;
 use terrain::{
-    river_gen::RiverGen, river_gen::RiverGenSettings, river_gen::RiverNode,
+    river_gen::NodeKind, river_gen::RiverGen, river_gen::RiverGenSettings, river_gen::RiverNode,
     slope_map::ArraySlopeMap,
 };
 
 use svg::node::element::path::Data;
-use svg::node::element::Path;
+use svg::node::element::{Circle, Group, Path};
 use svg::Document;
 
 // M-18.182745,294.74779L90.913728,296.7681L175.76654000000002,246.26048L266.68027,264.44322L335.37064,321.01176L381.83765999999997,304.84932L442.44682,203.83405999999997L529.31994,163.42795999999996L519.2184,304.8493199999999L525.27932,417.9864099999999L509.11688,502.83921999999995L511.13719,581.63112L579.82756,694.7682L600.0306099999999,828.10834L519.21841,925.08299L411.13208999999995,964.47893L284.86301999999995,967.5093899999999L143.44164999999995,955.3875599999999L56.56854199999995,910.9407999999999L-24.243661000000046,854.3722999999999L-101.01525000000005,830.1286499999999L-105.05586000000005,733.1539999999999L-123.23861000000005,615.9763099999999L-171.72593000000006,472.5346499999999L-115.15739000000006,365.4584699999999Z
@@ -65,22 +66,27 @@ fn main() {
     graph.add_node(RiverNode {
         pos: Point3::new(222.85715, 283.79078, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(442.85714, 240.93363, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(590.0, 420.0, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(328.57143, 500.93364, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(188.57143, 509.50506, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
 
     let settings = RiverGenSettings {
@@ -92,17 +98,28 @@ fn main() {
 
         edge_length: 2000.0,
         edge_margin: 1500.0,
-    };
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
-    let data = vec![
-        0.0, 0.1, 0.1, 0.0,
-        0.1, 0.2, 0.3, 0.1,
-        0.0, 0.1, 0.2, 0.0,
-        0.1, 0.1, 0.0, 0.0,
-    ];
+        // Lakes are off, raise these to place them.
+        prob_lake: 0.0,
+        lake_slope: 0.0,
+        lake_radius: 600.0,
+    };
 
-    let slope_map = ArraySlopeMap::new(data, 4, na::Vector2::new(45.0, 215.0), 595.0 * SCALE);
+    // A slope map painted in an image editor can be passed as the first argument,
+    // it needs a world file next to it, see `ArraySlopeMap::load`.
+    let slope_map = if let Some(path) = env::args_os().nth(1) {
+        ArraySlopeMap::load(path).expect("failed to load slope map")
+    } else {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.1, 0.1, 0.0,
+            0.1, 0.2, 0.3, 0.1,
+            0.0, 0.1, 0.2, 0.0,
+            0.1, 0.1, 0.0, 0.0,
+        ];
+
+        ArraySlopeMap::new(data, 4, na::Vector2::new(45.0, 215.0), 595.0 * SCALE)
+    };
 
     let mut gen = RiverGen::new(
         SmallRng::from_entropy(),
@@ -127,6 +144,19 @@ fn main() {
         .set("stroke-width", 40)
         .set("d", river_data);
 
+    let lakes = gen
+        .graph
+        .node_indices()
+        .fold(Group::new(), |g, idx| match gen.graph[idx].kind {
+            NodeKind::Lake { radius } => g.add(
+                Circle::new()
+                    .set("cx", gen.graph[idx].pos.x)
+                    .set("cy", gen.graph[idx].pos.y)
+                    .set("r", radius),
+            ),
+            NodeKind::River => g,
+        }).set("fill", "#0a9fff");
+
     let contour_data = CONTOUR
         .iter()
         .take(1)
@@ -148,7 +178,8 @@ fn main() {
             "viewBox",
             (45.0 * SCALE, 215.0 * SCALE, 595.0 * SCALE, 425.0 * SCALE),
         ).add(contour_path)
-        .add(river_path);
+        .add(river_path)
+        .add(lakes);
 
     svg::write(io::stdout(), &document).unwrap();
 
@@ -1,7 +1,3 @@
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-
 extern crate nalgebra as na;
 extern crate petgraph;
 extern crate rand;
@@ -20,7 +16,7 @@ use petgraph::stable_graph::StableGraph;
 use rand::prelude::*;
 use rand::rngs::SmallRng;
 use terrain::{
-    river_gen::RiverGen, river_gen::RiverGenSettings, river_gen::RiverNode,
+    river_gen::NodeKind, river_gen::RiverGen, river_gen::RiverGenSettings, river_gen::RiverNode,
     slope_map::ArraySlopeMap,
 };
 
@@ -61,17 +57,6 @@ const SCALE: f64 = 100.0;
 
 fn main() {}
 
-#[derive(Deserialize)]
-struct InitialRiverNodes {
-    pos: (f64, f64),
-    priority: u32,
-}
-
-#[derive(Deserialize)]
-struct RiverGeneratorSettings {
-    initial_river_nodes: Vec<InitialRiverNodes>,
-}
-
 #[cfg_attr(
     all(target_arch = "wasm32", target_os = "unknown"),
     js_export
@@ -98,44 +83,52 @@ pub fn generate_river(
     let contour = contour
         .as_slice()
         .chunks(2)
-        .map(|c| Point2::new(c[0] as f64, c[1] as f64))
+        .map(|c| Point2::new(c[0], c[1]))
         .collect();
 
     let mut graph = StableGraph::new();
     graph.add_node(RiverNode {
         pos: Point3::new(222.85715, 283.79078, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(442.85714, 240.93363, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(590.0, 420.0, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(328.57143, 500.93364, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
     graph.add_node(RiverNode {
         pos: Point3::new(188.57143, 509.50506, 0.0) * SCALE,
         priority: 20,
+        kind: NodeKind::River,
     });
 
     let settings = RiverGenSettings {
         height_range: 2.0,
 
-        prob_growth: prob_growth as f64,
-        prob_symmetric: prob_symmetric as f64,
-        prob_asymetric: prob_asymetric as f64,
+        prob_growth,
+        prob_symmetric,
+        prob_asymetric,
 
         edge_length: 2000.0,
         edge_margin: 1500.0,
+
+        prob_lake: 0.0,
+        lake_slope: 0.0,
+        lake_radius: 0.0,
     };
 
     let slope_map_size = (slope_map.len() as f64).sqrt().round() as usize;
-    let slope_map = slope_map.iter().map(|&x| x as f64).collect();
     let slope_map = ArraySlopeMap::new(
         slope_map,
         slope_map_size,
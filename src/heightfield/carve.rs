@@ -0,0 +1,437 @@
+//! Carving the channels and valleys of a river network into an existing heightfield.
+
+use petgraph::graph::EdgeIndex;
+use petgraph::stable_graph::StableGraph;
+use petgraph::visit::{DfsPostOrder, EdgeRef};
+use petgraph::Direction;
+
+use std::collections::HashMap;
+use std::{f32, f64};
+
+use heightfield::Heightfield;
+use primitives::{ChannelProfile, RiverPrimitive, TerrainPrimitive};
+use river_classifier::{self, RiverEdge, RiverType};
+use river_gen;
+use {Point2, Point3, Vector3};
+
+/// The channel along one edge of a river network.
+#[derive(Clone, Copy, Debug)]
+pub struct Channel {
+    pub width: f64,
+    pub depth: f64,
+    pub profile: ChannelProfile,
+}
+
+#[derive(Clone, Debug)]
+pub struct PriorityChannelSettings {
+    /// Width of a channel for each step of the priority of its upstream node.
+    ///
+    /// **Example value:** 5.0
+    pub width_per_priority: f64,
+
+    /// Depth of a channel relative to its width.
+    ///
+    /// **Example value:** 0.1
+    pub depth_ratio: f64,
+
+    pub profile: ChannelProfile,
+}
+
+/// Channels with the widths and depths of the classified network and the profiles of their river
+/// types. Edges without a profile are left out, which splits the network there.
+pub fn classified_channels(
+    graph: &StableGraph<river_classifier::RiverNode, RiverEdge>,
+    profiles: &HashMap<RiverType, ChannelProfile>,
+) -> StableGraph<Point2, Channel> {
+    graph.filter_map(
+        |_, node| Some(Point2::new(node.pos.x, node.pos.y)),
+        |_, edge| {
+            let profile = edge.rosgen.and_then(|rosgen| profiles.get(&rosgen))?;
+            Some(Channel {
+                width: edge.width,
+                depth: edge.depth,
+                profile: *profile,
+            })
+        },
+    )
+}
+
+/// Channels for a network straight from `RiverGen`, sized by the priorities of the nodes.
+pub fn priority_channels(
+    graph: &StableGraph<river_gen::RiverNode, ()>,
+    settings: &PriorityChannelSettings,
+) -> StableGraph<Point2, Channel> {
+    graph.map(
+        |_, node| Point2::new(node.pos.x, node.pos.y),
+        |idx, _| {
+            let (_, upstream) = graph.edge_endpoints(idx).unwrap();
+            let width = f64::from(graph[upstream].priority) * settings.width_per_priority;
+            Channel {
+                width,
+                depth: width * settings.depth_ratio,
+                profile: settings.profile,
+            }
+        },
+    )
+}
+
+impl Heightfield {
+    /// Carves the channels into the heightfield, the edges of `channels` point upstream.
+    ///
+    /// The bed is followed down each channel from its source, staying the depth of the channel
+    /// below the heightfield and dropping by at least `min_drop` along every edge. The valleys
+    /// along the beds are blended into the heightfield, which is only ever lowered, and the
+    /// samples under the center lines are then lowered to the lowest sample upstream of them, so
+    /// they only ever go down towards the outlets even where valleys of different depths
+    /// overlap.
+    pub fn carve_channels(&mut self, channels: &StableGraph<Point2, Channel>, min_drop: f64) {
+        assert!(min_drop >= 0.0);
+
+        let beds = self.channel_beds(channels, min_drop);
+
+        for &(idx, ref bed) in &beds {
+            let channel = channels[idx];
+            for pair in bed.windows(2) {
+                // The water surface is one channel depth above the bed.
+                let river = RiverPrimitive {
+                    a: pair[1] + Vector3::z() * channel.depth,
+                    b: pair[0] + Vector3::z() * channel.depth,
+                    width: channel.width,
+                    depth: channel.depth,
+                    profile: channel.profile,
+                };
+                self.carve(&river);
+            }
+        }
+
+        // Draining one channel can lower the samples of another where they cross, so this
+        // repeats until no sample changes. Samples are only ever set to the value of another
+        // sample, which bounds the number of rounds.
+        while beds
+            .iter()
+            .fold(false, |changed, (_, bed)| self.drain(bed) || changed)
+        {}
+    }
+
+    /// The beds along each edge, from the upstream node to the downstream node in steps of half
+    /// a cell. Edges come after the edges upstream of them.
+    fn channel_beds(
+        &self,
+        channels: &StableGraph<Point2, Channel>,
+        min_drop: f64,
+    ) -> Vec<(EdgeIndex, Vec<Point3>)> {
+        let roots = channels
+            .node_indices()
+            .filter(|&idx| {
+                channels
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .next()
+                    .is_none()
+            })
+            .collect::<Vec<_>>();
+
+        let mut node_beds = HashMap::with_capacity(channels.node_count());
+        let mut beds = Vec::with_capacity(channels.edge_count());
+        for root in roots {
+            let mut dfs = DfsPostOrder::new(channels, root);
+            while let Some(idx) = dfs.next(channels) {
+                let pos = channels[idx];
+                let depth = channels
+                    .edges_directed(idx, Direction::Incoming)
+                    .chain(channels.edges_directed(idx, Direction::Outgoing))
+                    .map(|edge| edge.weight().depth)
+                    .fold(0.0, f64::max);
+                let mut node_bed = self.sample(pos) - depth;
+
+                let upstream_edges = channels
+                    .edges_directed(idx, Direction::Outgoing)
+                    .map(|edge| (edge.id(), edge.target()))
+                    .collect::<Vec<_>>();
+                for &(edge, upstream) in &upstream_edges {
+                    let start = channels[upstream];
+                    let steps = ((pos - start).norm() / (self.cell_size * 0.5))
+                        .ceil()
+                        .max(1.0);
+
+                    let mut bed = node_beds[&upstream];
+                    let mut points = vec![Point3::new(start.x, start.y, bed)];
+                    for step in 1..=steps as usize {
+                        let p = start + (pos - start) * (step as f64 / steps);
+                        bed = (bed - min_drop / steps).min(self.sample(p) - channels[edge].depth);
+                        points.push(Point3::new(p.x, p.y, bed));
+                    }
+
+                    node_bed = node_bed.min(bed);
+                    beds.push((edge, points));
+                }
+
+                // Every edge ends at the lowest bed of the node.
+                let first = beds.len() - upstream_edges.len();
+                for &mut (_, ref mut points) in &mut beds[first..] {
+                    points.last_mut().unwrap().z = node_bed;
+                }
+                node_beds.insert(idx, node_bed);
+            }
+        }
+
+        beds
+    }
+
+    /// Lowers the samples closest to the points of `bed`, from upstream to downstream, to the
+    /// lowest of them so far. Returns whether any sample changed.
+    fn drain(&mut self, bed: &[Point3]) -> bool {
+        let mut changed = false;
+        let mut lowest = f32::INFINITY;
+        for p in bed {
+            if let Some(idx) = self.nearest(Point2::new(p.x, p.y)) {
+                let sample = &mut self.data[idx];
+                if *sample > lowest {
+                    *sample = lowest;
+                    changed = true;
+                } else {
+                    lowest = *sample;
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Index of the sample closest to `pos`, `None` outside of the heightfield.
+    fn nearest(&self, pos: Point2) -> Option<usize> {
+        let x = ((pos.x - self.origin.x) / self.cell_size).round();
+        let y = ((pos.y - self.origin.y) / self.cell_size).round();
+        if (0.0..self.width as f64).contains(&x) && (0.0..self.height as f64).contains(&y) {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Lowers the samples towards the primitive by its weight, like `BlendTree::carve`.
+    fn carve<P: TerrainPrimitive>(&mut self, primitive: &P) {
+        let bb = primitive.bounding_box();
+        let last_x = self.width as f64 - 1.0;
+        let last_y = self.height as f64 - 1.0;
+
+        let x0 = ((bb.min.x - self.origin.x) / self.cell_size)
+            .ceil()
+            .max(0.0);
+        let x1 = ((bb.max.x - self.origin.x) / self.cell_size)
+            .floor()
+            .min(last_x);
+        let y0 = ((bb.min.y - self.origin.y) / self.cell_size)
+            .ceil()
+            .max(0.0);
+        let y1 = ((bb.max.y - self.origin.y) / self.cell_size)
+            .floor()
+            .min(last_y);
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        for y in y0 as usize..=y1 as usize {
+            for x in x0 as usize..=x1 as usize {
+                let pos = self.position(x, y);
+                let weight = primitive.weight(pos);
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                let sample = &mut self.data[y * self.width + x];
+                let depth = (f64::from(*sample) - primitive.elevation(pos)).max(0.0);
+                *sample = (f64::from(*sample) - depth * weight) as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use primitives::CrossSection;
+    use river_gen::NodeKind;
+
+    fn profile() -> ChannelProfile {
+        ChannelProfile {
+            cross_section: CrossSection::Parabolic,
+            valley_width: 4.0,
+            bank_slope: 0.2,
+        }
+    }
+
+    /// Bumpy ground rising away from the coast at y = 0.
+    fn heightfield() -> Heightfield {
+        let (width, height) = (101, 101);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x, y) = (x as f64, y as f64);
+                data.push((y * 0.5 + 8.0 * (x * 0.2).sin() * (y * 0.15).cos()) as f32);
+            }
+        }
+
+        Heightfield::new(width, height, 1.0, Point2::new(0.0, 0.0), data)
+    }
+
+    #[test]
+    fn monotonic_beds() {
+        let mut channels = StableGraph::new();
+        let mouth = channels.add_node(Point2::new(50.0, 2.0));
+        let fork = channels.add_node(Point2::new(50.0, 40.0));
+        let left = channels.add_node(Point2::new(10.0, 40.0));
+        let right = channels.add_node(Point2::new(50.0, 95.0));
+
+        let channel = |width| Channel {
+            width,
+            depth: width * 0.2,
+            profile: profile(),
+        };
+        channels.add_edge(mouth, fork, channel(6.0));
+        channels.add_edge(fork, left, channel(4.0));
+        channels.add_edge(fork, right, channel(4.0));
+
+        let original = heightfield();
+        let mut carved = original.clone();
+        carved.carve_channels(&channels, 0.5);
+
+        // only ever lowered
+        for (&c, &o) in carved.data.iter().zip(&original.data) {
+            assert!(c <= o);
+        }
+
+        assert_draining(&carved, &channels);
+
+        // at least the channel depth below the ground at the nodes
+        for &idx in &[mouth, fork, left, right] {
+            let pos = channels[idx];
+            assert!(carved.sample(pos) <= original.sample(pos) - 0.8 + 1e-3);
+        }
+
+        // the ground away from the rivers is untouched
+        let far = (90, 10);
+        assert_eq!(carved.get(far.0, far.1), original.get(far.0, far.1));
+    }
+
+    #[test]
+    fn diagonal_channels() {
+        let mut channels = StableGraph::new();
+        let mouth = channels.add_node(Point2::new(5.0, 2.0));
+        let fork = channels.add_node(Point2::new(45.0, 42.0));
+        let left = channels.add_node(Point2::new(10.0, 80.0));
+        let right = channels.add_node(Point2::new(90.0, 93.0));
+
+        let channel = Channel {
+            width: 4.0,
+            depth: 0.8,
+            profile: profile(),
+        };
+        channels.add_edge(mouth, fork, channel);
+        channels.add_edge(fork, left, channel);
+        channels.add_edge(fork, right, channel);
+
+        let original = heightfield();
+        let mut carved = original.clone();
+        carved.carve_channels(&channels, 0.5);
+
+        assert_draining(&carved, &channels);
+
+        // Between the cells the samples mix in the banks of the channel, up to half a cell
+        // diagonal from the center line.
+        let bank = channel.depth * 0.5 / (channel.width * 0.5).powi(2);
+        for (_, bed) in original.channel_beds(&channels, 0.5) {
+            for pair in bed.windows(2) {
+                let pos = Point2::new(pair[0].x, pair[0].y);
+                let nearby = bed
+                    .iter()
+                    .filter(|p| (Point2::new(p.x, p.y) - pos).norm() <= 1.5)
+                    .map(|p| p.z)
+                    .fold(f64::NEG_INFINITY, f64::max);
+                let ground = carved.sample(pos);
+                assert!(
+                    ground <= nearby + bank + 1e-6,
+                    "{} above the bed {} at {:?}",
+                    ground,
+                    nearby,
+                    pos
+                );
+            }
+        }
+        assert!(carved.sample(channels[mouth]) < carved.sample(channels[left]));
+        assert!(carved.sample(channels[mouth]) < carved.sample(channels[right]));
+    }
+
+    /// Checks that the samples under the center lines of the carved heightfield go down from
+    /// the sources to the outlets. The beds only give the positions along the center lines.
+    fn assert_draining(carved: &Heightfield, channels: &StableGraph<Point2, Channel>) {
+        for (_, bed) in carved.channel_beds(channels, 0.0) {
+            let mut previous = f32::INFINITY;
+            for p in bed {
+                let pos = Point2::new(p.x, p.y);
+                let sample = carved.data[carved.nearest(pos).unwrap()];
+                assert!(sample <= previous, "bed rises at {:?}", pos);
+                previous = sample;
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_valleys() {
+        // a shallow stream running down next to a deep river, inside its valley
+        let mut channels = StableGraph::new();
+        let mouth = channels.add_node(Point2::new(50.0, 2.0));
+        let fork = channels.add_node(Point2::new(50.0, 60.0));
+        let source = channels.add_node(Point2::new(50.0, 98.0));
+        let stream = channels.add_node(Point2::new(62.0, 10.0));
+
+        let channel = |width, depth| Channel {
+            width,
+            depth,
+            profile: profile(),
+        };
+        channels.add_edge(mouth, fork, channel(8.0, 4.0));
+        channels.add_edge(fork, source, channel(8.0, 4.0));
+        channels.add_edge(fork, stream, channel(2.0, 0.2));
+
+        let original = heightfield();
+        let mut carved = original.clone();
+        carved.carve_channels(&channels, 0.1);
+
+        for (&c, &o) in carved.data.iter().zip(&original.data) {
+            assert!(c <= o);
+        }
+        assert_draining(&carved, &channels);
+    }
+
+    #[test]
+    fn channels_from_priorities() {
+        let mut graph = StableGraph::new();
+        let node = |y, priority| river_gen::RiverNode {
+            pos: Point3::new(10.0, y, 0.0),
+            priority,
+            kind: NodeKind::River,
+        };
+        let mouth = graph.add_node(node(0.0, 4));
+        let source = graph.add_node(node(50.0, 2));
+        graph.add_edge(mouth, source, ());
+
+        let settings = PriorityChannelSettings {
+            width_per_priority: 3.0,
+            depth_ratio: 0.5,
+            profile: profile(),
+        };
+        let channels = priority_channels(&graph, &settings);
+        let edge = channels.edge_indices().next().unwrap();
+        assert_eq!(channels[edge].width, 6.0);
+        assert_eq!(channels[edge].depth, 3.0);
+        assert_eq!(channels[source], Point2::new(10.0, 50.0));
+
+        let original = heightfield();
+        let mut carved = original.clone();
+        carved.carve_channels(&channels, 0.0);
+
+        let pos = Point2::new(10.0, 25.0);
+        assert!(carved.sample(pos) < original.sample(pos));
+    }
+}
@@ -0,0 +1,349 @@
+//! Rasters derived from a heightfield for texturing: normals, slope, aspect, curvature and
+//! ambient occlusion.
+
+use png;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use std::f32;
+use std::io::{self, Write};
+
+use heightfield::Heightfield;
+use slope_map::{encode_png, BitDepth};
+
+/// A raster with the same layout as the heightfield it was derived from, stored row by row.
+#[derive(Clone, Debug)]
+pub struct Grid<T> {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn get(&self, x: usize, y: usize) -> T {
+        self.data[y * self.width + x]
+    }
+}
+
+impl Grid<f32> {
+    /// Writes the grid as a grayscale PNG, `min` is black and `max` is white. Values outside the
+    /// range are clamped and missing values, NaN, are black.
+    pub fn write_png<W: Write>(
+        &self,
+        writer: W,
+        min: f32,
+        max: f32,
+        depth: BitDepth,
+    ) -> io::Result<()> {
+        assert!(max > min);
+
+        let values = self
+            .data
+            .iter()
+            .map(|&v| f64::from((v - min) / (max - min)));
+        encode_png(
+            writer,
+            self.width,
+            self.height,
+            png::ColorType::Grayscale,
+            values,
+            depth,
+        )
+    }
+}
+
+impl Grid<[f32; 3]> {
+    /// Writes the normals as an RGB PNG, each component mapped from [-1.0, 1.0] to a channel.
+    pub fn write_png<W: Write>(&self, writer: W, depth: BitDepth) -> io::Result<()> {
+        let values = self.data.iter().flat_map(|n| {
+            n.iter()
+                .map(|&c| f64::from(c) * 0.5 + 0.5)
+                .collect::<Vec<_>>()
+        });
+        encode_png(
+            writer,
+            self.width,
+            self.height,
+            png::ColorType::RGB,
+            values,
+            depth,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AmbientOcclusionSettings {
+    /// Number of directions searched for the horizon around each sample.
+    ///
+    /// **Example value:** 16
+    pub directions: usize,
+
+    /// How far the horizon is searched for.
+    ///
+    /// **Example value:** 2000.0
+    pub distance: f64,
+}
+
+/// First and second order partial derivatives of the elevation.
+#[derive(Clone, Copy, Debug)]
+struct Derivatives {
+    dx: f64,
+    dy: f64,
+    dxx: f64,
+    dyy: f64,
+    dxy: f64,
+}
+
+impl Heightfield {
+    /// Derivatives from central differences, the samples along the edges are repeated outside
+    /// of the heightfield.
+    fn derivatives(&self, x: usize, y: usize) -> Derivatives {
+        let h = |dx: isize, dy: isize| {
+            let x = (x as isize + dx).max(0).min(self.width as isize - 1) as usize;
+            let y = (y as isize + dy).max(0).min(self.height as isize - 1) as usize;
+            f64::from(self.get(x, y))
+        };
+        let c = self.cell_size;
+
+        Derivatives {
+            dx: (h(1, 0) - h(-1, 0)) / (2.0 * c),
+            dy: (h(0, 1) - h(0, -1)) / (2.0 * c),
+            dxx: (h(1, 0) - 2.0 * h(0, 0) + h(-1, 0)) / (c * c),
+            dyy: (h(0, 1) - 2.0 * h(0, 0) + h(0, -1)) / (c * c),
+            dxy: (h(1, 1) - h(1, -1) - h(-1, 1) + h(-1, -1)) / (4.0 * c * c),
+        }
+    }
+
+    fn derived<T, F>(&self, f: F) -> Grid<T>
+    where
+        T: Send,
+        F: Fn(usize, usize) -> T + Sync,
+    {
+        let width = self.width;
+        #[cfg(feature = "parallel")]
+        let samples = (0..self.width * self.height).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let samples = 0..self.width * self.height;
+        let data = samples.map(|idx| f(idx % width, idx / width)).collect();
+
+        Grid {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    /// Unit normals in the frame of the heightfield, x and y along the grid and z up.
+    pub fn normal_map(&self) -> Grid<[f32; 3]> {
+        self.derived(|x, y| {
+            let d = self.derivatives(x, y);
+            let length = (d.dx * d.dx + d.dy * d.dy + 1.0).sqrt();
+            [
+                (-d.dx / length) as f32,
+                (-d.dy / length) as f32,
+                (1.0 / length) as f32,
+            ]
+        })
+    }
+
+    /// Angle of the slope in radians, 0.0 where flat.
+    pub fn slope_angle(&self) -> Grid<f32> {
+        self.derived(|x, y| {
+            let d = self.derivatives(x, y);
+            (d.dx * d.dx + d.dy * d.dy).sqrt().atan() as f32
+        })
+    }
+
+    /// Direction the slope faces, the downhill direction in radians counter-clockwise from the
+    /// x axis in [0.0, 2π). Flat samples have no aspect and are NaN.
+    pub fn aspect(&self) -> Grid<f32> {
+        self.derived(|x, y| {
+            let d = self.derivatives(x, y);
+            if d.dx == 0.0 && d.dy == 0.0 {
+                return f32::NAN;
+            }
+
+            let angle = (-d.dy).atan2(-d.dx) as f32;
+            if angle < 0.0 {
+                angle + 2.0 * f32::consts::PI
+            } else {
+                angle
+            }
+        })
+    }
+
+    /// Curvature along the slope. Positive where the slope gets steeper downhill, convex
+    /// shoulders, negative in concave foot slopes and valley floors.
+    pub fn profile_curvature(&self) -> Grid<f32> {
+        self.derived(|x, y| {
+            let d = self.derivatives(x, y);
+            let p = d.dx * d.dx + d.dy * d.dy;
+            if p == 0.0 {
+                return 0.0;
+            }
+
+            let curvature = d.dxx * d.dx * d.dx + 2.0 * d.dxy * d.dx * d.dy + d.dyy * d.dy * d.dy;
+            (-curvature / (p * (1.0 + p).powf(1.5))) as f32
+        })
+    }
+
+    /// Curvature of the contour lines. Positive on ridges where flow diverges, negative in
+    /// valleys where it converges.
+    pub fn plan_curvature(&self) -> Grid<f32> {
+        self.derived(|x, y| {
+            let d = self.derivatives(x, y);
+            let p = d.dx * d.dx + d.dy * d.dy;
+            if p == 0.0 {
+                return 0.0;
+            }
+
+            let curvature = d.dxx * d.dy * d.dy - 2.0 * d.dxy * d.dx * d.dy + d.dyy * d.dx * d.dx;
+            (-curvature / p.powf(1.5)) as f32
+        })
+    }
+
+    /// An estimate of the ambient light reaching each sample in [0.0, 1.0], from the elevation
+    /// angle of the horizon in a number of directions. Open ground is 1.0.
+    pub fn ambient_occlusion(&self, settings: &AmbientOcclusionSettings) -> Grid<f32> {
+        assert!(settings.directions > 0);
+
+        let steps = (settings.distance / self.cell_size).ceil().max(1.0) as usize;
+        let directions = (0..settings.directions)
+            .map(|i| {
+                let angle = 2.0 * ::std::f64::consts::PI * i as f64 / settings.directions as f64;
+                (angle.cos(), angle.sin())
+            })
+            .collect::<Vec<_>>();
+
+        self.derived(|x, y| {
+            let z = f64::from(self.get(x, y));
+            let occlusion = directions
+                .iter()
+                .map(|&(dx, dy)| {
+                    // Tangent of the highest elevation angle along the direction.
+                    let mut horizon = 0.0_f64;
+                    for step in 1..=steps {
+                        let sx = (x as f64 + dx * step as f64).round();
+                        let sy = (y as f64 + dy * step as f64).round();
+                        if sx < 0.0
+                            || sy < 0.0
+                            || sx >= self.width as f64
+                            || sy >= self.height as f64
+                        {
+                            break;
+                        }
+
+                        let rise = f64::from(self.get(sx as usize, sy as usize)) - z;
+                        horizon = horizon.max(rise / (step as f64 * self.cell_size));
+                    }
+
+                    // Sine of the horizon angle.
+                    horizon / (1.0 + horizon * horizon).sqrt()
+                })
+                .sum::<f64>();
+
+            (1.0 - occlusion / directions.len() as f64) as f32
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Point2;
+
+    fn heightfield<F: Fn(f64, f64) -> f64>(size: usize, f: F) -> Heightfield {
+        let mut data = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                data.push(f(x as f64, y as f64) as f32);
+            }
+        }
+
+        Heightfield::new(size, size, 1.0, Point2::new(0.0, 0.0), data)
+    }
+
+    #[test]
+    fn plane() {
+        // rising along x, facing towards -x
+        let plane = heightfield(5, |x, _| x);
+
+        let normal = plane.normal_map().get(2, 2);
+        let expected = 0.5_f32.sqrt();
+        assert!((normal[0] + expected).abs() < 1e-6);
+        assert_eq!(normal[1], 0.0);
+        assert!((normal[2] - expected).abs() < 1e-6);
+
+        let slope = plane.slope_angle().get(2, 2);
+        assert!((slope - f32::consts::FRAC_PI_4).abs() < 1e-6);
+
+        let aspect = plane.aspect().get(2, 2);
+        assert!((aspect - f32::consts::PI).abs() < 1e-6);
+
+        assert_eq!(plane.profile_curvature().get(2, 2), 0.0);
+        assert_eq!(plane.plan_curvature().get(2, 2), 0.0);
+
+        let flat = heightfield(3, |_, _| 1.0);
+        assert!(flat.aspect().get(1, 1).is_nan());
+        assert_eq!(flat.slope_angle().get(1, 1), 0.0);
+    }
+
+    #[test]
+    fn curvature() {
+        // a ridge along y, convex across
+        let ridge = heightfield(9, |x, y| -(x - 4.0) * (x - 4.0) * 0.1 + y * 0.5);
+        assert!(ridge.profile_curvature().get(6, 4).abs() > 0.0);
+        assert!(ridge.plan_curvature().get(6, 4) > 0.0);
+
+        // a valley
+        let valley = heightfield(9, |x, y| (x - 4.0) * (x - 4.0) * 0.1 + y * 0.5);
+        assert!(valley.plan_curvature().get(6, 4) < 0.0);
+
+        // a shoulder, steeper downhill
+        let shoulder = heightfield(9, |x, _| -x * x * 0.1);
+        assert!(shoulder.profile_curvature().get(4, 4) > 0.0);
+        let foot = heightfield(9, |x, _| (x - 8.0) * (x - 8.0) * 0.1);
+        assert!(foot.profile_curvature().get(4, 4) < 0.0);
+    }
+
+    #[test]
+    fn ambient_occlusion() {
+        let settings = AmbientOcclusionSettings {
+            directions: 8,
+            distance: 4.0,
+        };
+
+        // open ground
+        let flat = heightfield(9, |_, _| 0.0);
+        assert_eq!(flat.ambient_occlusion(&settings).get(4, 4), 1.0);
+
+        // the bottom of a pit is darker than its rim
+        let pit = heightfield(9, |x, y| ((x - 4.0).powi(2) + (y - 4.0).powi(2)).sqrt());
+        let ao = pit.ambient_occlusion(&settings);
+        assert!(ao.get(4, 4) < ao.get(0, 4));
+        assert!(ao.get(4, 4) > 0.0);
+    }
+
+    #[test]
+    fn write_images() {
+        let plane = heightfield(4, |x, y| x + y);
+
+        let mut buf = Vec::new();
+        plane
+            .slope_angle()
+            .write_png(&mut buf, 0.0, f32::consts::FRAC_PI_2, BitDepth::Sixteen)
+            .unwrap();
+        let (info, _) = png::Decoder::new(&buf[..]).read_info().unwrap();
+        assert_eq!((info.width, info.height), (4, 4));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+
+        let mut buf = Vec::new();
+        plane
+            .normal_map()
+            .write_png(&mut buf, BitDepth::Eight)
+            .unwrap();
+        let (info, _) = png::Decoder::new(&buf[..]).read_info().unwrap();
+        assert_eq!(info.color_type, png::ColorType::RGB);
+    }
+}
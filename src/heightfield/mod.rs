@@ -0,0 +1,335 @@
+//! Rasterisation of the terrain into a regular grid of elevations.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use primitives::{BoundingBox, TerrainPrimitive};
+use {pnpoly, Point2};
+
+mod carve;
+mod maps;
+
+pub use self::carve::{classified_channels, priority_channels, Channel, PriorityChannelSettings};
+pub use self::maps::{AmbientOcclusionSettings, Grid};
+
+#[derive(Clone, Debug)]
+pub struct HeightfieldSettings {
+    /// Distance between two samples of the grid.
+    ///
+    /// **Example value:** 100.0
+    pub cell_size: f64,
+
+    /// Elevation of the samples outside the contour.
+    ///
+    /// **Example value:** 0.0
+    pub sea_level: f64,
+
+    /// Elevation of the samples inside the contour where no primitive has a weight.
+    ///
+    /// **Example value:** 0.0
+    pub base_elevation: f64,
+}
+
+/// Elevations sampled over a regular grid.
+///
+/// Samples are stored row by row, sample `(x, y)` is taken at `origin + (x, y) * cell_size`.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: f64,
+    pub origin: Point2,
+    pub data: Vec<f32>,
+}
+
+impl Heightfield {
+    pub fn new(
+        width: usize,
+        height: usize,
+        cell_size: f64,
+        origin: Point2,
+        data: Vec<f32>,
+    ) -> Heightfield {
+        assert_eq!(width * height, data.len());
+        assert!(width > 0 && height > 0);
+        assert!(cell_size > 0.0);
+
+        Heightfield {
+            width,
+            height,
+            cell_size,
+            origin,
+            data,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[y * self.width + x]
+    }
+
+    /// Position of sample `(x, y)`.
+    pub fn position(&self, x: usize, y: usize) -> Point2 {
+        Point2::new(
+            self.origin.x + x as f64 * self.cell_size,
+            self.origin.y + y as f64 * self.cell_size,
+        )
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(self.origin, self.position(self.width - 1, self.height - 1))
+    }
+
+    /// Elevation at `pos`, bilinearly interpolated between the samples. Positions outside the
+    /// grid are clamped to its edges.
+    pub fn sample(&self, pos: Point2) -> f64 {
+        let x = ((pos.x - self.origin.x) / self.cell_size)
+            .max(0.0)
+            .min((self.width - 1) as f64);
+        let y = ((pos.y - self.origin.y) / self.cell_size)
+            .max(0.0)
+            .min((self.height - 1) as f64);
+
+        let x0 = (x.floor() as usize).min(self.width.saturating_sub(2));
+        let y0 = (y.floor() as usize).min(self.height.saturating_sub(2));
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let tx = x - x0 as f64;
+        let ty = y - y0 as f64;
+
+        let a = f64::from(self.get(x0, y0));
+        let b = f64::from(self.get(x1, y0));
+        let c = f64::from(self.get(x0, y1));
+        let d = f64::from(self.get(x1, y1));
+
+        let top = a + (b - a) * tx;
+        let bottom = c + (d - c) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+/// Samples the terrain over the bounding box of the contour, the rows are evaluated in parallel.
+/// Samples outside the contour are set to the sea level, samples without any weight to the base
+/// elevation.
+pub fn rasterize<P: TerrainPrimitive + Sync>(
+    terrain: &P,
+    contour: &[Point2],
+    settings: &HeightfieldSettings,
+) -> Heightfield {
+    assert!(!contour.is_empty());
+    assert!(settings.cell_size > 0.0);
+
+    let bounding_box = contour
+        .iter()
+        .skip(1)
+        .fold(BoundingBox::new(contour[0], contour[0]), |bb, &p| {
+            bb.union(&BoundingBox::new(p, p))
+        });
+    let size = bounding_box.max - bounding_box.min;
+    let width = (size.x / settings.cell_size).ceil() as usize + 1;
+    let height = (size.y / settings.cell_size).ceil() as usize + 1;
+
+    let origin = bounding_box.min;
+    let position = |x, y| {
+        Point2::new(
+            origin.x + x as f64 * settings.cell_size,
+            origin.y + y as f64 * settings.cell_size,
+        )
+    };
+    let data = sample_grid(
+        terrain,
+        contour,
+        settings.sea_level,
+        settings.base_elevation,
+        width,
+        height,
+        position,
+    );
+
+    Heightfield::new(width, height, settings.cell_size, origin, data)
+}
+
+/// Samples the terrain at `position(x, y)` for every sample of a `width` by `height` grid, the
+/// rows are evaluated in parallel with the `parallel` feature. Samples outside the contour are
+/// set to the sea level, samples without any weight to the base elevation.
+pub(crate) fn sample_grid<P, F>(
+    terrain: &P,
+    contour: &[Point2],
+    sea_level: f64,
+    base_elevation: f64,
+    width: usize,
+    height: usize,
+    position: F,
+) -> Vec<f32>
+where
+    P: TerrainPrimitive + Sync,
+    F: Fn(usize, usize) -> Point2 + Sync,
+{
+    let verts = contour
+        .iter()
+        .cloned()
+        .zip(contour.iter().cloned().cycle().skip(1));
+
+    let sample_row = |(y, row): (usize, &mut [f32])| {
+        for (x, sample) in row.iter_mut().enumerate() {
+            let pos = position(x, y);
+            if !pnpoly(verts.clone(), pos) {
+                continue;
+            }
+
+            let (elevation, weight) = terrain.evaluate(pos);
+            *sample = if weight > 0.0 {
+                elevation as f32
+            } else {
+                base_elevation as f32
+            };
+        }
+    };
+
+    let mut data = vec![sea_level as f32; width * height];
+    #[cfg(feature = "parallel")]
+    data.par_chunks_mut(width).enumerate().for_each(sample_row);
+    #[cfg(not(feature = "parallel"))]
+    data.chunks_mut(width).enumerate().for_each(sample_row);
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use primitives::{BlendTree, MountainPrimitive, MountainShape};
+    use Point3;
+
+    struct Slope;
+
+    impl TerrainPrimitive for Slope {
+        fn elevation(&self, pos: Point2) -> f64 {
+            pos.x + 2.0 * pos.y
+        }
+
+        fn weight(&self, _pos: Point2) -> f64 {
+            1.0
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            BoundingBox::new(Point2::new(-1e9, -1e9), Point2::new(1e9, 1e9))
+        }
+    }
+
+    /// Flat at 5.0 left of x = 50.
+    struct Plateau;
+
+    impl TerrainPrimitive for Plateau {
+        fn elevation(&self, _pos: Point2) -> f64 {
+            5.0
+        }
+
+        fn weight(&self, pos: Point2) -> f64 {
+            if pos.x < 50.0 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            BoundingBox::new(Point2::new(-1e9, -1e9), Point2::new(50.0, 1e9))
+        }
+    }
+
+    #[test]
+    fn rasterize_triangle() {
+        let contour = vec![
+            Point2::new(10.0, 10.0),
+            Point2::new(105.0, 10.0),
+            Point2::new(10.0, 50.0),
+        ];
+        let settings = HeightfieldSettings {
+            cell_size: 10.0,
+            sea_level: -1.0,
+            base_elevation: 0.0,
+        };
+
+        let heightfield = rasterize(&Slope, &contour, &settings);
+        assert_eq!(heightfield.width, 11);
+        assert_eq!(heightfield.height, 5);
+        assert_eq!(heightfield.origin, Point2::new(10.0, 10.0));
+
+        // inside the contour
+        assert_eq!(heightfield.position(1, 1), Point2::new(20.0, 20.0));
+        assert_eq!(heightfield.get(1, 1), 60.0);
+
+        // outside the contour
+        assert_eq!(heightfield.get(10, 4), -1.0);
+        assert_eq!(heightfield.get(8, 3), -1.0);
+    }
+
+    #[test]
+    fn rasterize_without_weight() {
+        // a plateau covering the left half of the contour
+        let terrain = BlendTree::blend(vec![BlendTree::primitive(Plateau)]);
+
+        let contour = vec![
+            Point2::new(0.0, 0.0),
+            Point2::new(100.0, 0.0),
+            Point2::new(100.0, 100.0),
+            Point2::new(0.0, 100.0),
+        ];
+        let settings = HeightfieldSettings {
+            cell_size: 10.0,
+            sea_level: -1.0,
+            base_elevation: 2.0,
+        };
+
+        let heightfield = rasterize(&terrain, &contour, &settings);
+        assert_eq!(heightfield.get(2, 5), 5.0);
+        assert_eq!(heightfield.get(8, 5), 2.0);
+    }
+
+    #[test]
+    fn rasterize_bare_primitive() {
+        // the cone keeps falling off beyond its radius, where it has no weight
+        let mountain = MountainPrimitive {
+            peak: Point3::new(20.0, 50.0, 100.0),
+            radius: 30.0,
+            slope: 1.0,
+            shape: MountainShape::Cone,
+        };
+
+        let contour = vec![
+            Point2::new(0.0, 0.0),
+            Point2::new(100.0, 0.0),
+            Point2::new(100.0, 100.0),
+            Point2::new(0.0, 100.0),
+        ];
+        let settings = HeightfieldSettings {
+            cell_size: 10.0,
+            sea_level: -1.0,
+            base_elevation: 2.0,
+        };
+
+        let heightfield = rasterize(&mountain, &contour, &settings);
+        assert_eq!(heightfield.get(2, 5), 100.0);
+        assert_eq!(heightfield.get(4, 5), 80.0);
+        assert_eq!(heightfield.get(8, 5), 2.0);
+    }
+
+    #[test]
+    fn sample_bilinear() {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 1.0,
+            2.0, 3.0,
+        ];
+        let heightfield = Heightfield::new(2, 2, 10.0, Point2::new(5.0, 5.0), data);
+
+        assert_eq!(heightfield.sample(Point2::new(5.0, 5.0)), 0.0);
+        assert_eq!(heightfield.sample(Point2::new(15.0, 15.0)), 3.0);
+        assert_eq!(heightfield.sample(Point2::new(10.0, 10.0)), 1.5);
+        assert_eq!(heightfield.sample(Point2::new(10.0, 5.0)), 0.5);
+
+        // clamped outside
+        assert_eq!(heightfield.sample(Point2::new(-100.0, 100.0)), 2.0);
+    }
+}
@@ -1,23 +1,30 @@
-#![feature(slice_patterns)]
-
 extern crate alga;
 extern crate delaunator;
 extern crate nalgebra as na;
 extern crate petgraph;
+extern crate png;
 extern crate rand;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 extern crate serde;
-#[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "plot")]
 extern crate criterion_plot as plot;
+#[cfg(feature = "plot")]
+extern crate svg;
 
 pub type Point2 = na::Point2<f64>;
 pub type Point3 = na::Point3<f64>;
 pub type Vector2 = na::Vector2<f64>;
 pub type Vector3 = na::Vector3<f64>;
 
+pub mod heightfield;
+pub mod mesh;
+pub mod primitives;
 pub mod river_classifier;
 pub mod river_gen;
 pub mod slope_map;
+pub mod tiles;
 
 /**
  * Copyright (c) 1970-2003, Wm. Randolph Franklin
@@ -0,0 +1,361 @@
+//! Indexed triangle meshes of the terrain.
+
+use std::collections::HashSet;
+
+use delaunator;
+
+use heightfield::Heightfield;
+use primitives::{BoundingBox, TerrainPrimitive};
+use {Point2, Point3, Vector3};
+
+/// An indexed triangle mesh. Every three indices form a triangle, counter-clockwise when seen
+/// from above.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vector3>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    fn new(positions: Vec<Point3>, indices: Vec<u32>) -> Mesh {
+        let normals = vertex_normals(&positions, &indices);
+        Mesh {
+            positions,
+            normals,
+            indices,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveMeshSettings {
+    /// Distance between the points of the grid the refinement starts from.
+    ///
+    /// **Example value:** 1000.0
+    pub initial_spacing: f64,
+
+    /// Refinement stops once no triangle deviates more than this from the terrain.
+    ///
+    /// **Example value:** 1.0
+    pub max_error: f64,
+
+    /// Refinement stops before the mesh would get more triangles than this.
+    ///
+    /// **Example value:** 100000
+    pub max_triangles: usize,
+}
+
+/// A mesh with a vertex at every sample of the heightfield and two triangles per cell.
+pub fn heightfield_mesh(heightfield: &Heightfield) -> Mesh {
+    let (width, height) = (heightfield.width, heightfield.height);
+
+    let mut positions = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let pos = heightfield.position(x, y);
+            positions.push(Point3::new(pos.x, pos.y, f64::from(heightfield.get(x, y))));
+        }
+    }
+
+    Mesh::new(positions, grid_indices(width, height))
+}
+
+/// Two triangles for every cell of a grid of vertices stored row by row.
+pub(crate) fn grid_indices(width: usize, height: usize) -> Vec<u32> {
+    let mut indices = Vec::with_capacity(width.saturating_sub(1) * height.saturating_sub(1) * 6);
+    for y in 1..height {
+        for x in 1..width {
+            let a = ((y - 1) * width + x - 1) as u32;
+            let b = a + 1;
+            let c = a + width as u32;
+            let d = c + 1;
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+
+    indices
+}
+
+/// A Delaunay triangulation of points chosen where the terrain deviates the most from the
+/// mesh, which puts more triangles along rivers and ridges than on flat ground.
+///
+/// Starts from a grid over the bounding box and repeatedly inserts the sample with the largest
+/// error of each of the worst triangles, either its centroid or the midpoint of one of its edges.
+/// Refinement also stops once a round adds no triangles, as it would along a cliff where the
+/// error never drops.
+pub fn adaptive_mesh<P: TerrainPrimitive>(
+    terrain: &P,
+    bounding_box: &BoundingBox,
+    settings: &AdaptiveMeshSettings,
+) -> Mesh {
+    assert!(settings.initial_spacing > 0.0);
+    assert!(settings.max_triangles >= 2);
+
+    let size = bounding_box.max - bounding_box.min;
+    let columns = ((size.x / settings.initial_spacing).ceil() as usize).max(1);
+    let rows = ((size.y / settings.initial_spacing).ceil() as usize).max(1);
+
+    let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+    for y in 0..=rows {
+        for x in 0..=columns {
+            points.push(Point2::new(
+                bounding_box.min.x + size.x * x as f64 / columns as f64,
+                bounding_box.min.y + size.y * y as f64 / rows as f64,
+            ));
+        }
+    }
+
+    let mut positions = points
+        .iter()
+        .map(|p| Point3::new(p.x, p.y, terrain.elevation(*p)))
+        .collect::<Vec<_>>();
+    let mut triangles = triangulate(&positions);
+    let mut known = points
+        .iter()
+        .map(|p| (p.x.to_bits(), p.y.to_bits()))
+        .collect::<HashSet<_>>();
+
+    loop {
+        // Every inserted point adds at most two triangles.
+        let budget = settings.max_triangles.saturating_sub(triangles.len() / 3) / 2;
+        if budget == 0 {
+            break;
+        }
+
+        let mut candidates = triangles
+            .chunks(3)
+            .map(|t| worst_sample(terrain, [positions[t[0]], positions[t[1]], positions[t[2]]]))
+            .filter(|&(_, error)| error > settings.max_error)
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            break;
+        }
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        // Neighbouring triangles can pick the midpoint of the edge they share, and a sample can
+        // be a vertex already.
+        let mut inserted = 0;
+        for &(pos, _) in &candidates {
+            if inserted >= budget {
+                break;
+            }
+            if known.insert((pos.x.to_bits(), pos.y.to_bits())) {
+                positions.push(Point3::new(pos.x, pos.y, terrain.elevation(pos)));
+                inserted += 1;
+            }
+        }
+        if inserted == 0 {
+            break;
+        }
+
+        let count = triangles.len();
+        triangles = triangulate(&positions);
+        if triangles.len() == count {
+            break;
+        }
+    }
+
+    let positions = remove_unused(positions, &mut triangles);
+    let indices = triangles.into_iter().map(|i| i as u32).collect();
+    Mesh::new(positions, indices)
+}
+
+/// Drops the positions that no triangle uses, such as points too close to others for the
+/// triangulation to keep, and renumbers the triangles.
+fn remove_unused(positions: Vec<Point3>, triangles: &mut [usize]) -> Vec<Point3> {
+    let mut used = vec![false; positions.len()];
+    for &i in triangles.iter() {
+        used[i] = true;
+    }
+
+    let mut remap = vec![0; positions.len()];
+    let mut kept = Vec::with_capacity(positions.len());
+    for (i, pos) in positions.into_iter().enumerate() {
+        if used[i] {
+            remap[i] = kept.len();
+            kept.push(pos);
+        }
+    }
+
+    for i in triangles.iter_mut() {
+        *i = remap[*i];
+    }
+
+    kept
+}
+
+/// The centroid or edge midpoint of the triangle where the terrain is the furthest from the
+/// triangle, with the distance.
+fn worst_sample<P: TerrainPrimitive>(terrain: &P, triangle: [Point3; 3]) -> (Point2, f64) {
+    let [a, b, c] = triangle;
+    let samples = [
+        (a + b.coords + c.coords) / 3.0,
+        (a + b.coords) / 2.0,
+        (b + c.coords) / 2.0,
+        (c + a.coords) / 2.0,
+    ];
+
+    samples
+        .iter()
+        .map(|p| {
+            let pos = Point2::new(p.x, p.y);
+            (pos, (terrain.elevation(pos) - p.z).abs())
+        }).fold((Point2::new(a.x, a.y), -1.0), |worst, sample| {
+            if sample.1 > worst.1 {
+                sample
+            } else {
+                worst
+            }
+        })
+}
+
+/// Delaunay triangles of the positions seen from above, counter-clockwise.
+fn triangulate(positions: &[Point3]) -> Vec<usize> {
+    let points = positions
+        .iter()
+        .map(|p| delaunator::Point { x: p.x, y: p.y })
+        .collect::<Vec<_>>();
+
+    let mut triangles = match delaunator::triangulate(&points) {
+        Some(triangulation) => triangulation.triangles,
+        None => return Vec::new(),
+    };
+
+    for t in triangles.chunks_mut(3) {
+        let (a, b, c) = (positions[t[0]], positions[t[1]], positions[t[2]]);
+        if (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x) < 0.0 {
+            t.swap(1, 2);
+        }
+    }
+
+    triangles
+}
+
+/// Normals as the sum of the normals of the adjacent triangles, weighted by their areas.
+fn vertex_normals(positions: &[Point3], indices: &[u32]) -> Vec<Vector3> {
+    let mut normals = vec![Vector3::zeros(); positions.len()];
+    for t in indices.chunks(3) {
+        let (a, b, c) = (t[0] as usize, t[1] as usize, t[2] as usize);
+        let normal = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.norm() > 0.0 {
+                n.normalize()
+            } else {
+                Vector3::z()
+            }
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Terrain<F>(F);
+
+    impl<F: Fn(Point2) -> f64> TerrainPrimitive for Terrain<F> {
+        fn elevation(&self, pos: Point2) -> f64 {
+            (self.0)(pos)
+        }
+
+        fn weight(&self, _pos: Point2) -> f64 {
+            1.0
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            BoundingBox::new(Point2::new(-1e9, -1e9), Point2::new(1e9, 1e9))
+        }
+    }
+
+    fn assert_counter_clockwise(mesh: &Mesh) {
+        for t in mesh.indices.chunks(3) {
+            let a = mesh.positions[t[0] as usize];
+            let b = mesh.positions[t[1] as usize];
+            let c = mesh.positions[t[2] as usize];
+            assert!((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x) > 0.0);
+        }
+    }
+
+    #[test]
+    fn mesh_from_heightfield() {
+        // a plane rising along x
+        let data = (0..12).map(|i| (i % 4) as f32 * 10.0).collect();
+        let heightfield = Heightfield::new(4, 3, 10.0, Point2::new(0.0, 0.0), data);
+
+        let mesh = heightfield_mesh(&heightfield);
+        assert_eq!(mesh.positions.len(), 12);
+        assert_eq!(mesh.triangle_count(), 12);
+        assert_eq!(mesh.positions[5], Point3::new(10.0, 10.0, 10.0));
+        assert_counter_clockwise(&mesh);
+
+        let expected = Vector3::new(-1.0, 0.0, 1.0).normalize();
+        for normal in &mesh.normals {
+            assert!((normal - expected).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn adaptive_refinement() {
+        // flat ground with a narrow valley along x = 50
+        let terrain = Terrain(|pos: Point2| -50.0 * (-(pos.x - 50.0).powi(2) / 20.0).exp());
+        let bounding_box = BoundingBox::new(Point2::new(0.0, 0.0), Point2::new(100.0, 100.0));
+
+        let settings = AdaptiveMeshSettings {
+            initial_spacing: 25.0,
+            max_error: 0.5,
+            max_triangles: 500,
+        };
+        let mesh = adaptive_mesh(&terrain, &bounding_box, &settings);
+        assert!(mesh.triangle_count() <= 500);
+        assert!(mesh.triangle_count() > 32);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+        assert_counter_clockwise(&mesh);
+
+        // the new points are along the valley
+        let near = mesh.positions[25..]
+            .iter()
+            .filter(|p| (p.x - 50.0).abs() < 15.0)
+            .count();
+        assert!(near * 2 > mesh.positions.len() - 25);
+
+        // a plane needs no refinement
+        let plane = Terrain(|pos: Point2| pos.x + pos.y);
+        let mesh = adaptive_mesh(&plane, &bounding_box, &settings);
+        assert_eq!(mesh.positions.len(), 25);
+        assert_eq!(mesh.triangle_count(), 32);
+    }
+
+    #[test]
+    fn refinement_along_a_cliff() {
+        // the error along the cliff never drops below the maximum
+        let terrain = Terrain(|pos: Point2| if pos.x < 100.0 / 3.0 { 0.0 } else { 10.0 });
+        let bounding_box = BoundingBox::new(Point2::new(0.0, 0.0), Point2::new(100.0, 100.0));
+
+        let settings = AdaptiveMeshSettings {
+            initial_spacing: 25.0,
+            max_error: 0.1,
+            max_triangles: 2000,
+        };
+        let mesh = adaptive_mesh(&terrain, &bounding_box, &settings);
+        assert!(mesh.triangle_count() <= 2000);
+        assert_counter_clockwise(&mesh);
+
+        // every vertex belongs to a triangle
+        let mut used = vec![false; mesh.positions.len()];
+        for &i in &mesh.indices {
+            used[i as usize] = true;
+        }
+        assert!(used.into_iter().all(|u| u));
+    }
+}
@@ -0,0 +1,459 @@
+//! The construction tree that combines primitives into the elevation of the terrain.
+
+use primitives::{gradient, BoundingBox, Bvh, Gradient, TerrainPrimitive};
+use {Point2, Vector2, Vector3};
+
+/// A node of the construction tree, a primitive or an operator on other nodes. The tree is a
+/// primitive itself, so trees can be nested.
+pub enum BlendTree {
+    Primitive(Box<dyn TerrainPrimitive + Send + Sync>),
+
+    /// The average of the elevations weighted by the weights of the children.
+    Blend(Bvh<BlendTree>),
+
+    /// The highest elevation of the children that have a weight.
+    Max(Bvh<BlendTree>),
+
+    /// The second node replaces the first where it has a weight, blended by its weight.
+    Replace(Box<BlendTree>, Box<BlendTree>),
+
+    /// The elevation of the second node scaled by its weight is added to the first.
+    Add(Box<BlendTree>, Box<BlendTree>),
+
+    /// Like `Replace`, but the second node can only lower the first, such as a river valley.
+    Carve(Box<BlendTree>, Box<BlendTree>),
+}
+
+impl BlendTree {
+    pub fn primitive<P: TerrainPrimitive + Send + Sync + 'static>(primitive: P) -> BlendTree {
+        BlendTree::Primitive(Box::new(primitive))
+    }
+
+    /// Children without a support are left out, they have no weight anywhere.
+    pub fn blend(children: Vec<BlendTree>) -> BlendTree {
+        BlendTree::Blend(Bvh::new(supported(children)))
+    }
+
+    /// Children without a support are left out, they have no weight anywhere.
+    pub fn max(children: Vec<BlendTree>) -> BlendTree {
+        BlendTree::Max(Bvh::new(supported(children)))
+    }
+
+    pub fn replace(self, other: BlendTree) -> BlendTree {
+        BlendTree::Replace(Box::new(self), Box::new(other))
+    }
+
+    pub fn plus(self, other: BlendTree) -> BlendTree {
+        BlendTree::Add(Box::new(self), Box::new(other))
+    }
+
+    pub fn carve(self, other: BlendTree) -> BlendTree {
+        BlendTree::Carve(Box::new(self), Box::new(other))
+    }
+
+    /// Box around the support of the tree, `None` for a tree without any primitives.
+    pub fn support(&self) -> Option<BoundingBox> {
+        match *self {
+            BlendTree::Primitive(ref primitive) => Some(primitive.bounding_box()),
+            BlendTree::Blend(ref children) | BlendTree::Max(ref children) => {
+                children.bounding_box()
+            }
+            BlendTree::Replace(ref base, ref other) | BlendTree::Carve(ref base, ref other) => {
+                match (base.support(), other.support()) {
+                    (Some(a), Some(b)) => Some(a.union(&b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            BlendTree::Add(ref base, _) => base.support(),
+        }
+    }
+
+    /// Elevation and weight at `pos`. The elevation is 0.0 where the weight is 0.0.
+    pub fn evaluate(&self, pos: Point2) -> (f64, f64) {
+        match *self {
+            BlendTree::Primitive(ref primitive) => {
+                if !primitive.bounding_box().contains(pos) {
+                    return (0.0, 0.0);
+                }
+
+                let weight = primitive.weight(pos);
+                if weight > 0.0 {
+                    (primitive.elevation(pos), weight)
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            BlendTree::Blend(ref children) => {
+                let mut sum = 0.0;
+                let mut total_weight = 0.0;
+                let mut max_weight = 0.0_f64;
+                children.query(pos, |child| {
+                    let (elevation, weight) = child.evaluate(pos);
+                    sum += elevation * weight;
+                    total_weight += weight;
+                    max_weight = max_weight.max(weight);
+                });
+
+                if total_weight > 0.0 {
+                    (sum / total_weight, max_weight)
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            BlendTree::Max(ref children) => {
+                let mut result = None::<(f64, f64)>;
+                children.query(pos, |child| {
+                    let (elevation, weight) = child.evaluate(pos);
+                    if weight > 0.0 {
+                        result = Some(match result {
+                            Some((e, w)) => (e.max(elevation), w.max(weight)),
+                            None => (elevation, weight),
+                        });
+                    }
+                });
+
+                result.unwrap_or((0.0, 0.0))
+            }
+            BlendTree::Replace(ref base, ref other) => {
+                let (base_elevation, base_weight) = base.evaluate(pos);
+                let (elevation, weight) = other.evaluate(pos);
+
+                if base_weight <= 0.0 {
+                    (elevation, weight)
+                } else {
+                    (
+                        base_elevation + (elevation - base_elevation) * weight,
+                        base_weight.max(weight),
+                    )
+                }
+            }
+            BlendTree::Add(ref base, ref other) => {
+                let (base_elevation, base_weight) = base.evaluate(pos);
+                let (elevation, weight) = other.evaluate(pos);
+
+                if base_weight <= 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    (base_elevation + elevation * weight, base_weight)
+                }
+            }
+            BlendTree::Carve(ref base, ref other) => {
+                let (base_elevation, base_weight) = base.evaluate(pos);
+                let (elevation, weight) = other.evaluate(pos);
+
+                if base_weight <= 0.0 {
+                    (elevation, weight)
+                } else {
+                    let depth = (base_elevation - elevation).max(0.0);
+                    (base_elevation - depth * weight, base_weight.max(weight))
+                }
+            }
+        }
+    }
+}
+
+impl BlendTree {
+    /// Elevation, weight and their gradients at `pos` in a single traversal of the tree.
+    /// Primitives without analytic gradients fall back to finite differences.
+    pub fn evaluate_gradient(&self, pos: Point2) -> (f64, f64, Gradient) {
+        let none = (0.0, 0.0, Gradient::zeros());
+        match *self {
+            BlendTree::Primitive(ref primitive) => {
+                if !primitive.bounding_box().contains(pos) {
+                    return none;
+                }
+
+                let weight = primitive.weight(pos);
+                if weight > 0.0 {
+                    (primitive.elevation(pos), weight, gradient(primitive, pos))
+                } else {
+                    none
+                }
+            }
+            BlendTree::Blend(ref children) => {
+                let mut sum = 0.0;
+                let mut total_weight = 0.0;
+                let mut max_weight = (0.0, Vector2::zeros());
+                let mut sum_gradient = Gradient::zeros();
+                children.query(pos, |child| {
+                    let (elevation, weight, g) = child.evaluate_gradient(pos);
+                    sum += elevation * weight;
+                    total_weight += weight;
+                    sum_gradient.elevation += g.elevation * weight + g.weight * elevation;
+                    sum_gradient.weight += g.weight;
+                    if weight > max_weight.0 {
+                        max_weight = (weight, g.weight);
+                    }
+                });
+
+                if total_weight > 0.0 {
+                    let elevation = sum / total_weight;
+                    let g = Gradient {
+                        elevation: (sum_gradient.elevation - sum_gradient.weight * elevation)
+                            / total_weight,
+                        weight: max_weight.1,
+                    };
+                    (elevation, max_weight.0, g)
+                } else {
+                    none
+                }
+            }
+            BlendTree::Max(ref children) => {
+                let mut result = None::<(f64, f64, Gradient)>;
+                children.query(pos, |child| {
+                    let (elevation, weight, g) = child.evaluate_gradient(pos);
+                    if weight > 0.0 {
+                        result = Some(match result {
+                            Some((e, w, mut max)) => {
+                                if elevation > e {
+                                    max.elevation = g.elevation;
+                                }
+                                if weight > w {
+                                    max.weight = g.weight;
+                                }
+                                (e.max(elevation), w.max(weight), max)
+                            }
+                            None => (elevation, weight, g),
+                        });
+                    }
+                });
+
+                result.unwrap_or(none)
+            }
+            BlendTree::Replace(ref base, ref other) => {
+                let (base_elevation, base_weight, base_g) = base.evaluate_gradient(pos);
+                let (elevation, weight, g) = other.evaluate_gradient(pos);
+
+                if base_weight <= 0.0 {
+                    (elevation, weight, g)
+                } else {
+                    let gradient = Gradient {
+                        elevation: base_g.elevation
+                            + (g.elevation - base_g.elevation) * weight
+                            + g.weight * (elevation - base_elevation),
+                        weight: if weight > base_weight {
+                            g.weight
+                        } else {
+                            base_g.weight
+                        },
+                    };
+                    (
+                        base_elevation + (elevation - base_elevation) * weight,
+                        base_weight.max(weight),
+                        gradient,
+                    )
+                }
+            }
+            BlendTree::Add(ref base, ref other) => {
+                let (base_elevation, base_weight, base_g) = base.evaluate_gradient(pos);
+                let (elevation, weight, g) = other.evaluate_gradient(pos);
+
+                if base_weight <= 0.0 {
+                    none
+                } else {
+                    let gradient = Gradient {
+                        elevation: base_g.elevation + g.elevation * weight + g.weight * elevation,
+                        weight: base_g.weight,
+                    };
+                    (base_elevation + elevation * weight, base_weight, gradient)
+                }
+            }
+            BlendTree::Carve(ref base, ref other) => {
+                let (base_elevation, base_weight, base_g) = base.evaluate_gradient(pos);
+                let (elevation, weight, g) = other.evaluate_gradient(pos);
+
+                if base_weight <= 0.0 {
+                    (elevation, weight, g)
+                } else {
+                    let depth = base_elevation - elevation;
+                    let mut gradient = Gradient {
+                        elevation: base_g.elevation,
+                        weight: if weight > base_weight {
+                            g.weight
+                        } else {
+                            base_g.weight
+                        },
+                    };
+                    if depth > 0.0 {
+                        gradient.elevation -=
+                            (base_g.elevation - g.elevation) * weight + g.weight * depth;
+                    }
+                    (
+                        base_elevation - depth.max(0.0) * weight,
+                        base_weight.max(weight),
+                        gradient,
+                    )
+                }
+            }
+        }
+    }
+
+    /// Elevation and unit normal of the surface at `pos`.
+    pub fn elevation_normal(&self, pos: Point2) -> (f64, Vector3) {
+        let (elevation, _, gradient) = self.evaluate_gradient(pos);
+        (elevation, gradient.normal())
+    }
+}
+
+impl TerrainPrimitive for BlendTree {
+    fn elevation(&self, pos: Point2) -> f64 {
+        self.evaluate(pos).0
+    }
+
+    fn weight(&self, pos: Point2) -> f64 {
+        self.evaluate(pos).1
+    }
+
+    fn evaluate(&self, pos: Point2) -> (f64, f64) {
+        BlendTree::evaluate(self, pos)
+    }
+
+    /// See `support`, a tree without any primitives has an empty box at the origin.
+    fn bounding_box(&self) -> BoundingBox {
+        self.support()
+            .unwrap_or_else(|| BoundingBox::new(Point2::origin(), Point2::origin()))
+    }
+
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        Some(self.evaluate_gradient(pos).2)
+    }
+}
+
+fn supported(children: Vec<BlendTree>) -> Vec<BlendTree> {
+    children
+        .into_iter()
+        .filter(|child| child.support().is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat disc with full weight.
+    struct Disc {
+        center: Point2,
+        radius: f64,
+        elevation: f64,
+    }
+
+    impl TerrainPrimitive for Disc {
+        fn elevation(&self, _pos: Point2) -> f64 {
+            self.elevation
+        }
+
+        fn weight(&self, pos: Point2) -> f64 {
+            if (pos - self.center).norm() < self.radius {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            BoundingBox::new(self.center, self.center).expand(self.radius)
+        }
+    }
+
+    /// A plane with weight falling off linearly from 1.0 at the origin to 0.0 at x = 10.
+    struct Ramp {
+        elevation: f64,
+    }
+
+    impl TerrainPrimitive for Ramp {
+        fn elevation(&self, _pos: Point2) -> f64 {
+            self.elevation
+        }
+
+        fn weight(&self, pos: Point2) -> f64 {
+            (1.0 - pos.x / 10.0).clamp(0.0, 1.0)
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            BoundingBox::new(Point2::new(0.0, -10.0), Point2::new(10.0, 10.0))
+        }
+    }
+
+    fn disc(x: f64, radius: f64, elevation: f64) -> BlendTree {
+        BlendTree::primitive(Disc {
+            center: Point2::new(x, 0.0),
+            radius,
+            elevation,
+        })
+    }
+
+    #[test]
+    fn blend_and_max() {
+        let children = || {
+            vec![
+                disc(0.0, 2.0, 10.0),
+                disc(1.0, 2.0, 20.0),
+                disc(20.0, 1.0, 5.0),
+            ]
+        };
+
+        let blend = BlendTree::blend(children());
+        assert_eq!(blend.evaluate(Point2::new(0.5, 0.0)), (15.0, 1.0));
+        assert_eq!(blend.evaluate(Point2::new(-1.5, 0.0)), (10.0, 1.0));
+        assert_eq!(blend.evaluate(Point2::new(10.0, 0.0)), (0.0, 0.0));
+
+        let max = BlendTree::max(children());
+        assert_eq!(max.evaluate(Point2::new(0.5, 0.0)), (20.0, 1.0));
+        assert_eq!(max.evaluate(Point2::new(20.0, 0.0)), (5.0, 1.0));
+
+        let bb = max.bounding_box();
+        assert_eq!(bb.min, Point2::new(-2.0, -2.0));
+        assert_eq!(bb.max, Point2::new(21.0, 2.0));
+    }
+
+    #[test]
+    fn replace_add_and_carve() {
+        let base = || disc(0.0, 100.0, 10.0);
+        let ramp = |elevation| BlendTree::primitive(Ramp { elevation });
+
+        let replace = base().replace(ramp(20.0));
+        assert_eq!(replace.evaluate(Point2::new(0.0, 0.0)), (20.0, 1.0));
+        assert_eq!(replace.evaluate(Point2::new(5.0, 0.0)), (15.0, 1.0));
+        assert_eq!(replace.evaluate(Point2::new(50.0, 0.0)), (10.0, 1.0));
+
+        let add = base().plus(ramp(4.0));
+        assert_eq!(add.evaluate(Point2::new(5.0, 0.0)), (12.0, 1.0));
+        assert_eq!(add.bounding_box(), base().bounding_box());
+
+        // carving only lowers the terrain
+        let carve = base().carve(ramp(4.0));
+        assert_eq!(carve.evaluate(Point2::new(5.0, 0.0)), (7.0, 1.0));
+        let carve = base().carve(ramp(20.0));
+        assert_eq!(carve.evaluate(Point2::new(5.0, 0.0)), (10.0, 1.0));
+
+        // outside of the base, the other node is used as is
+        let carve = disc(100.0, 1.0, 10.0).carve(ramp(4.0));
+        assert_eq!(carve.evaluate(Point2::new(5.0, 0.0)), (4.0, 0.5));
+    }
+
+    #[test]
+    fn empty_trees() {
+        let empty = || BlendTree::blend(Vec::new());
+        assert_eq!(empty().support(), None);
+        assert_eq!(empty().evaluate(Point2::new(0.0, 0.0)), (0.0, 0.0));
+
+        // empty children do not stretch the boxes to the origin
+        let bb = disc(100.0, 1.0, 10.0).bounding_box();
+        assert_eq!(disc(100.0, 1.0, 10.0).carve(empty()).bounding_box(), bb);
+        assert_eq!(empty().carve(disc(100.0, 1.0, 10.0)).bounding_box(), bb);
+        assert_eq!(
+            BlendTree::blend(vec![empty(), disc(100.0, 1.0, 10.0)]).bounding_box(),
+            bb
+        );
+    }
+
+    #[test]
+    fn nested_trees() {
+        let rivers = BlendTree::blend((0..100).map(|i| disc(i as f64 * 10.0, 2.0, 1.0)).collect());
+        let terrain = disc(500.0, 1000.0, 50.0).carve(rivers);
+
+        assert_eq!(terrain.elevation(Point2::new(300.0, 0.0)), 1.0);
+        assert_eq!(terrain.elevation(Point2::new(305.0, 0.0)), 50.0);
+        assert_eq!(terrain.weight(Point2::new(2000.0, 0.0)), 0.0);
+    }
+}
@@ -0,0 +1,245 @@
+//! Bounding volume hierarchy over the supports of primitives.
+
+use primitives::{BoundingBox, TerrainPrimitive};
+use Point2;
+
+/// Number of primitives below which a node is not split further.
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf {
+        bounding_box: BoundingBox,
+        start: usize,
+        end: usize,
+    },
+    Inner {
+        bounding_box: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounding_box(&self) -> &BoundingBox {
+        match *self {
+            Node::Leaf {
+                ref bounding_box, ..
+            }
+            | Node::Inner {
+                ref bounding_box, ..
+            } => bounding_box,
+        }
+    }
+}
+
+/// A bounding volume hierarchy, built once by splitting the primitives in half along the longest
+/// axis of their bounding boxes.
+#[derive(Debug)]
+pub struct Bvh<P: TerrainPrimitive> {
+    primitives: Vec<P>,
+    bounding_boxes: Vec<BoundingBox>,
+    nodes: Vec<Node>,
+}
+
+impl<P: TerrainPrimitive> Bvh<P> {
+    pub fn new(primitives: Vec<P>) -> Bvh<P> {
+        let mut items = primitives
+            .into_iter()
+            .map(|p| (p.bounding_box(), p))
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::new();
+        if !items.is_empty() {
+            let len = items.len();
+            build(&mut items, 0, len, &mut nodes);
+        }
+
+        let (bounding_boxes, primitives) = items.into_iter().unzip();
+        Bvh {
+            primitives,
+            bounding_boxes,
+            nodes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Box around all primitives, `None` when there are none.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.nodes.first().map(|node| *node.bounding_box())
+    }
+
+    /// Calls `f` with every primitive whose bounding box contains `pos`.
+    pub fn query<F: FnMut(&P)>(&self, pos: Point2, mut f: F) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            match self.nodes[idx] {
+                Node::Leaf {
+                    ref bounding_box,
+                    start,
+                    end,
+                } => {
+                    if bounding_box.contains(pos) {
+                        for i in start..end {
+                            if self.bounding_boxes[i].contains(pos) {
+                                f(&self.primitives[i]);
+                            }
+                        }
+                    }
+                }
+                Node::Inner {
+                    ref bounding_box,
+                    left,
+                    right,
+                } => {
+                    if bounding_box.contains(pos) {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Builds the node for `items[start..end]` and its children, returns the index of the node.
+fn build<P>(
+    items: &mut [(BoundingBox, P)],
+    start: usize,
+    end: usize,
+    nodes: &mut Vec<Node>,
+) -> usize {
+    let bounding_box = items[start + 1..end]
+        .iter()
+        .fold(items[start].0, |bb, item| bb.union(&item.0));
+
+    let idx = nodes.len();
+    if end - start <= LEAF_SIZE {
+        nodes.push(Node::Leaf {
+            bounding_box,
+            start,
+            end,
+        });
+        return idx;
+    }
+
+    // Reserved, replaced once the children have been built.
+    nodes.push(Node::Leaf {
+        bounding_box,
+        start,
+        end,
+    });
+
+    let size = bounding_box.max - bounding_box.min;
+    let axis = if size.x >= size.y { 0 } else { 1 };
+    items[start..end].sort_by(|a, b| a.0.center()[axis].total_cmp(&b.0.center()[axis]));
+
+    let middle = start + (end - start) / 2;
+    let left = build(items, start, middle, nodes);
+    let right = build(items, middle, end, nodes);
+    nodes[idx] = Node::Inner {
+        bounding_box,
+        left,
+        right,
+    };
+
+    idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use Vector2;
+
+    struct Square(BoundingBox);
+
+    impl TerrainPrimitive for Square {
+        fn elevation(&self, _pos: Point2) -> f64 {
+            1.0
+        }
+
+        fn weight(&self, pos: Point2) -> f64 {
+            if self.0.contains(pos) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+
+        fn bounding_box(&self) -> BoundingBox {
+            self.0
+        }
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let mut rng = XorShiftRng::from_seed([3; 16]);
+        let squares = (0..500)
+            .map(|_| {
+                let min = Point2::new(rng.gen_range(0.0, 100.0), rng.gen_range(0.0, 100.0));
+                let size = rng.gen_range(0.5, 5.0);
+                Square(BoundingBox::new(min, min + Vector2::new(size, size)))
+            }).collect::<Vec<_>>();
+        let expected = |pos: Point2| squares.iter().filter(|s| s.0.contains(pos)).count();
+
+        let queries = (0..200)
+            .map(|_| Point2::new(rng.gen_range(0.0, 100.0), rng.gen_range(0.0, 100.0)))
+            .collect::<Vec<_>>();
+        let counts = queries.iter().map(|&p| expected(p)).collect::<Vec<_>>();
+
+        let bvh = Bvh::new(squares);
+        assert_eq!(bvh.len(), 500);
+        for (&pos, &count) in queries.iter().zip(&counts) {
+            let mut found = 0;
+            bvh.query(pos, |_| found += 1);
+            assert_eq!(found, count);
+        }
+
+        let bb = bvh.bounding_box().unwrap();
+        for square in bvh.primitives() {
+            assert!(bb.contains(square.0.min) && bb.contains(square.0.max));
+        }
+    }
+
+    #[test]
+    fn nan_bounding_boxes() {
+        let squares = (0..10)
+            .map(|i| {
+                let x = if i % 3 == 0 { f64::NAN } else { i as f64 };
+                Square(BoundingBox {
+                    min: Point2::new(x, 0.0),
+                    max: Point2::new(x + 1.0, 1.0),
+                })
+            }).collect::<Vec<_>>();
+
+        let bvh = Bvh::new(squares);
+        let mut found = 0;
+        bvh.query(Point2::new(4.5, 0.5), |_| found += 1);
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = Bvh::<Square>::new(Vec::new());
+        assert!(bvh.is_empty());
+        assert_eq!(bvh.bounding_box(), None);
+        bvh.query(Point2::new(0.0, 0.0), |_| panic!());
+    }
+}
@@ -0,0 +1,147 @@
+//! Lake primitives, a flat-bottomed basin under the flat surface of each lake node.
+
+use petgraph::stable_graph::StableGraph;
+
+use primitives::{BoundingBox, Gradient, TerrainPrimitive};
+use river_classifier::{RiverEdge, RiverNode};
+use river_gen::NodeKind;
+use {Point2, Point3};
+
+#[derive(Clone, Debug)]
+pub struct LakePrimitive {
+    /// Center of the lake at the elevation of its surface.
+    pub center: Point3,
+    pub radius: f64,
+    pub depth: f64,
+}
+
+impl LakePrimitive {
+    fn distance(&self, pos: Point2) -> f64 {
+        (pos - Point2::new(self.center.x, self.center.y)).norm()
+    }
+}
+
+impl TerrainPrimitive for LakePrimitive {
+    /// The bottom is flat over the inner half of the lake and rises to the surface at the shore.
+    /// Beyond the shore the primitive is flat at the surface, which flattens the shores when it
+    /// is carved into the terrain.
+    fn elevation(&self, pos: Point2) -> f64 {
+        let t = self.distance(pos) / self.radius;
+        let bottom = self.center.z - self.depth;
+
+        if t <= 0.5 {
+            bottom
+        } else if t < 1.0 {
+            bottom + self.depth * (t - 0.5) * 2.0
+        } else {
+            self.center.z
+        }
+    }
+
+    /// Full weight over the lake, falling off over the same distance again around it.
+    fn weight(&self, pos: Point2) -> f64 {
+        let t = self.distance(pos) / self.radius;
+
+        if t <= 1.0 {
+            1.0
+        } else if t < 2.0 {
+            let t = t - 1.0;
+            1.0 - t * t * (3.0 - 2.0 * t)
+        } else {
+            0.0
+        }
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let center = Point2::new(self.center.x, self.center.y);
+        BoundingBox::new(center, center).expand(self.radius * 2.0)
+    }
+
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        let offset = pos - Point2::new(self.center.x, self.center.y);
+        let distance = offset.norm();
+        if distance <= 0.0 {
+            return Some(Gradient::zeros());
+        }
+
+        let direction = offset / distance;
+        let t = distance / self.radius;
+
+        let elevation = if t > 0.5 && t < 1.0 {
+            2.0 * self.depth / self.radius
+        } else {
+            0.0
+        };
+        let weight = if t > 1.0 && t < 2.0 {
+            let t = t - 1.0;
+            -6.0 * t * (1.0 - t) / self.radius
+        } else {
+            0.0
+        };
+
+        Some(Gradient {
+            elevation: direction * elevation,
+            weight: direction * weight,
+        })
+    }
+}
+
+/// A lake primitive for every lake node of the classified network.
+pub fn lake_primitives(
+    graph: &StableGraph<RiverNode, RiverEdge>,
+    depth: f64,
+) -> Vec<LakePrimitive> {
+    graph
+        .node_indices()
+        .filter_map(|idx| match graph[idx].kind {
+            NodeKind::Lake { radius } if radius > 0.0 => Some(LakePrimitive {
+                center: graph[idx].pos,
+                radius,
+                depth,
+            }),
+            _ => None,
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_bottom() {
+        let lake = LakePrimitive {
+            center: Point3::new(0.0, 0.0, 100.0),
+            radius: 10.0,
+            depth: 4.0,
+        };
+
+        assert_eq!(lake.elevation(Point2::new(0.0, 0.0)), 96.0);
+        assert_eq!(lake.elevation(Point2::new(0.0, 5.0)), 96.0);
+        assert_eq!(lake.elevation(Point2::new(7.5, 0.0)), 98.0);
+        assert_eq!(lake.elevation(Point2::new(15.0, 0.0)), 100.0);
+
+        assert_eq!(lake.weight(Point2::new(10.0, 0.0)), 1.0);
+        assert_eq!(lake.weight(Point2::new(15.0, 0.0)), 0.5);
+        assert_eq!(lake.weight(Point2::new(20.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn lakes_from_nodes() {
+        let node = |x, kind| RiverNode {
+            pos: Point3::new(x, 0.0, 1.0),
+            kind,
+            area: 1.0,
+            cell: Vec::new(),
+            drainage_area: 1.0,
+        };
+
+        let mut graph = StableGraph::new();
+        graph.add_node(node(0.0, NodeKind::River));
+        graph.add_node(node(10.0, NodeKind::Lake { radius: 5.0 }));
+
+        let lakes = lake_primitives(&graph, 2.0);
+        assert_eq!(lakes.len(), 1);
+        assert_eq!(lakes[0].center, Point3::new(10.0, 0.0, 1.0));
+        assert_eq!(lakes[0].radius, 5.0);
+    }
+}
@@ -0,0 +1,423 @@
+//! Terrain primitives, the pieces of terrain that are combined into the final elevation.
+//!
+//! Each primitive has an elevation and a weight at every point. The weight is zero outside of the
+//! support of the primitive, which is contained in its bounding box.
+
+use na;
+
+use {Point2, Vector2, Vector3};
+
+mod blend;
+mod bvh;
+mod lake;
+mod mountain;
+mod noise;
+mod river;
+
+pub use self::blend::BlendTree;
+pub use self::bvh::Bvh;
+pub use self::lake::{lake_primitives, LakePrimitive};
+pub use self::mountain::{mountain_primitives, MountainPrimitive, MountainSettings, MountainShape};
+pub use self::noise::{ridged_noise, value_noise};
+pub use self::river::{
+    default_channel_profiles, river_primitives, ChannelProfile, CrossSection, RiverPrimitive,
+};
+
+pub trait TerrainPrimitive {
+    /// Elevation of the primitive at `pos`, only meaningful where the weight is above zero.
+    fn elevation(&self, pos: Point2) -> f64;
+
+    /// Weight in [0.0, 1.0] of the primitive at `pos`, zero outside of its support.
+    fn weight(&self, pos: Point2) -> f64;
+
+    /// The elevation and the weight at `pos` at once, for primitives that compute both together.
+    fn evaluate(&self, pos: Point2) -> (f64, f64) {
+        (self.elevation(pos), self.weight(pos))
+    }
+
+    /// Box around the support of the primitive.
+    fn bounding_box(&self) -> BoundingBox;
+
+    /// Analytic gradients at `pos`, `None` for primitives that do not have them. Use
+    /// [`gradient`](fn.gradient.html) to fall back to finite differences.
+    fn gradient(&self, _pos: Point2) -> Option<Gradient> {
+        None
+    }
+}
+
+/// Gradients of the elevation and the weight of a primitive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gradient {
+    pub elevation: Vector2,
+    pub weight: Vector2,
+}
+
+impl Gradient {
+    pub fn zeros() -> Gradient {
+        Gradient {
+            elevation: Vector2::zeros(),
+            weight: Vector2::zeros(),
+        }
+    }
+
+    /// Unit normal of the surface.
+    pub fn normal(&self) -> Vector3 {
+        Vector3::new(-self.elevation.x, -self.elevation.y, 1.0).normalize()
+    }
+}
+
+/// Distance between the samples of the finite differences.
+const GRADIENT_STEP: f64 = 0.01;
+
+/// Gradients of the primitive at `pos`, analytic where the primitive has them and from central
+/// differences otherwise.
+pub fn gradient<P: TerrainPrimitive + ?Sized>(primitive: &P, pos: Point2) -> Gradient {
+    primitive
+        .gradient(pos)
+        .unwrap_or_else(|| finite_differences(primitive, pos))
+}
+
+fn finite_differences<P: TerrainPrimitive + ?Sized>(primitive: &P, pos: Point2) -> Gradient {
+    let dx = Vector2::new(GRADIENT_STEP, 0.0);
+    let dy = Vector2::new(0.0, GRADIENT_STEP);
+    let difference = |f: &dyn Fn(Point2) -> f64| {
+        Vector2::new(f(pos + dx) - f(pos - dx), f(pos + dy) - f(pos - dy))
+    };
+
+    Gradient {
+        elevation: difference(&|p| primitive.elevation(p)) / (2.0 * GRADIENT_STEP),
+        weight: difference(&|p| primitive.weight(p)) / (2.0 * GRADIENT_STEP),
+    }
+}
+
+impl<P: TerrainPrimitive + ?Sized> TerrainPrimitive for &P {
+    fn elevation(&self, pos: Point2) -> f64 {
+        (**self).elevation(pos)
+    }
+
+    fn weight(&self, pos: Point2) -> f64 {
+        (**self).weight(pos)
+    }
+
+    fn evaluate(&self, pos: Point2) -> (f64, f64) {
+        (**self).evaluate(pos)
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        (**self).bounding_box()
+    }
+
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        (**self).gradient(pos)
+    }
+}
+
+impl<P: TerrainPrimitive + ?Sized> TerrainPrimitive for Box<P> {
+    fn elevation(&self, pos: Point2) -> f64 {
+        (**self).elevation(pos)
+    }
+
+    fn weight(&self, pos: Point2) -> f64 {
+        (**self).weight(pos)
+    }
+
+    fn evaluate(&self, pos: Point2) -> (f64, f64) {
+        (**self).evaluate(pos)
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        (**self).bounding_box()
+    }
+
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        (**self).gradient(pos)
+    }
+}
+
+/// The mountains blended together, with the river valleys and then the lakes carved into them.
+pub fn terrain_tree(
+    rivers: Vec<RiverPrimitive>,
+    mountains: Vec<MountainPrimitive>,
+    lakes: Vec<LakePrimitive>,
+) -> BlendTree {
+    let rivers = rivers.into_iter().map(BlendTree::primitive).collect();
+    let mountains = mountains.into_iter().map(BlendTree::primitive).collect();
+    let lakes = lakes.into_iter().map(BlendTree::primitive).collect();
+
+    BlendTree::blend(mountains)
+        .carve(BlendTree::blend(rivers))
+        .carve(BlendTree::blend(lakes))
+}
+
+/// An axis aligned box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point2,
+    pub max: Point2,
+}
+
+impl BoundingBox {
+    pub fn new(min: Point2, max: Point2) -> BoundingBox {
+        assert!(min.x <= max.x && min.y <= max.y);
+        BoundingBox { min, max }
+    }
+
+    /// The smallest box around two points.
+    pub fn from_points(a: Point2, b: Point2) -> BoundingBox {
+        BoundingBox {
+            min: Point2::new(a.x.min(b.x), a.y.min(b.y)),
+            max: Point2::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    /// Grows the box by `margin` on every side.
+    pub fn expand(&self, margin: f64) -> BoundingBox {
+        BoundingBox::new(
+            Point2::new(self.min.x - margin, self.min.y - margin),
+            Point2::new(self.max.x + margin, self.max.y + margin),
+        )
+    }
+
+    /// The smallest box around both boxes.
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point2::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: Point2::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    pub fn contains(&self, pos: Point2) -> bool {
+        pos.x >= self.min.x && pos.x <= self.max.x && pos.y >= self.min.y && pos.y <= self.max.y
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+
+    pub fn center(&self) -> Point2 {
+        na::center(&self.min, &self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use Point3;
+
+    /// Compares the analytic gradients with finite differences at random points around the
+    /// primitive.
+    fn assert_gradient<P: TerrainPrimitive>(primitive: &P, seed: u8) {
+        let mut rng = XorShiftRng::from_seed([seed; 16]);
+        let bb = primitive.bounding_box().expand(10.0);
+
+        for _ in 0..500 {
+            let pos = Point2::new(
+                rng.gen_range(bb.min.x, bb.max.x),
+                rng.gen_range(bb.min.y, bb.max.y),
+            );
+            let analytic = primitive.gradient(pos).expect("no analytic gradient");
+            let numeric = finite_differences(primitive, pos);
+
+            assert!(
+                (analytic.elevation - numeric.elevation).norm() < 1e-3,
+                "elevation at {:?}: {:?} != {:?}",
+                pos,
+                analytic.elevation,
+                numeric.elevation
+            );
+            assert!(
+                (analytic.weight - numeric.weight).norm() < 1e-3,
+                "weight at {:?}: {:?} != {:?}",
+                pos,
+                analytic.weight,
+                numeric.weight
+            );
+        }
+    }
+
+    #[test]
+    fn analytic_gradients() {
+        let shapes = [MountainShape::Cone, MountainShape::Dome];
+        for (i, &shape) in shapes.iter().enumerate() {
+            let mountain = MountainPrimitive {
+                peak: Point3::new(10.0, 20.0, 100.0),
+                radius: 100.0,
+                slope: 0.4,
+                shape,
+            };
+            assert_gradient(&mountain, i as u8 + 1);
+        }
+
+        let lake = LakePrimitive {
+            center: Point3::new(-10.0, 5.0, 20.0),
+            radius: 40.0,
+            depth: 3.0,
+        };
+        assert_gradient(&lake, 3);
+
+        let cross_sections = [
+            CrossSection::V,
+            CrossSection::Trapezoid,
+            CrossSection::Parabolic,
+            CrossSection::Flat,
+        ];
+        for (i, &cross_section) in cross_sections.iter().enumerate() {
+            let river = RiverPrimitive {
+                a: Point3::new(0.0, 0.0, 5.0),
+                b: Point3::new(80.0, 60.0, 15.0),
+                width: 12.0,
+                depth: 2.0,
+                profile: ChannelProfile {
+                    cross_section,
+                    valley_width: 6.0,
+                    bank_slope: 0.3,
+                },
+            };
+            assert_gradient(&river, i as u8 + 4);
+        }
+
+        // ridged mountains fall back to finite differences
+        let ridged = MountainPrimitive {
+            peak: Point3::new(0.0, 0.0, 100.0),
+            radius: 100.0,
+            slope: 0.4,
+            shape: MountainShape::Ridged {
+                height: 10.0,
+                wavelength: 20.0,
+                seed: 1,
+            },
+        };
+        let pos = Point2::new(30.0, 40.0);
+        assert_eq!(ridged.gradient(pos), None);
+        assert_eq!(gradient(&ridged, pos), finite_differences(&ridged, pos));
+    }
+
+    #[test]
+    fn tree_gradient() {
+        let river = RiverPrimitive {
+            a: Point3::new(0.0, 0.0, 0.0),
+            b: Point3::new(0.0, 1000.0, 10.0),
+            width: 20.0,
+            depth: 2.0,
+            profile: ChannelProfile {
+                cross_section: CrossSection::Parabolic,
+                valley_width: 10.0,
+                bank_slope: 0.1,
+            },
+        };
+        let mountain = |x, shape| MountainPrimitive {
+            peak: Point3::new(x, 500.0, 200.0),
+            radius: 1000.0,
+            slope: 0.3,
+            shape,
+        };
+        let lake = LakePrimitive {
+            center: Point3::new(0.0, 1000.0, 10.0),
+            radius: 50.0,
+            depth: 5.0,
+        };
+
+        let terrain = terrain_tree(
+            vec![river],
+            vec![
+                mountain(-500.0, MountainShape::Dome),
+                mountain(500.0, MountainShape::Cone),
+            ],
+            vec![lake],
+        );
+        assert_gradient(&terrain, 8);
+
+        let pos = Point2::new(200.0, 300.0);
+        let (elevation, normal) = terrain.elevation_normal(pos);
+        assert_eq!(elevation, terrain.elevation(pos));
+        assert!((normal.norm() - 1.0).abs() < 1e-12);
+        assert!(normal.z > 0.0);
+    }
+
+    #[test]
+    fn bounding_box() {
+        let a = BoundingBox::from_points(Point2::new(2.0, 0.0), Point2::new(0.0, 1.0));
+        assert_eq!(a.min, Point2::new(0.0, 0.0));
+        assert_eq!(a.max, Point2::new(2.0, 1.0));
+        assert_eq!(a.center(), Point2::new(1.0, 0.5));
+
+        let b = BoundingBox::new(Point2::new(3.0, 0.0), Point2::new(4.0, 1.0));
+        assert!(!a.intersects(&b));
+        assert!(a.expand(1.0).intersects(&b));
+        assert_eq!(a.union(&b).max, Point2::new(4.0, 1.0));
+
+        assert!(a.contains(Point2::new(2.0, 1.0)));
+        assert!(!a.contains(Point2::new(2.5, 1.0)));
+    }
+
+    #[test]
+    fn rivers_carved_into_mountains() {
+        let river = RiverPrimitive {
+            a: Point3::new(0.0, 0.0, 0.0),
+            b: Point3::new(0.0, 1000.0, 10.0),
+            width: 20.0,
+            depth: 2.0,
+            profile: ChannelProfile {
+                cross_section: CrossSection::Parabolic,
+                valley_width: 10.0,
+                bank_slope: 0.1,
+            },
+        };
+        let mountain = |x| MountainPrimitive {
+            peak: Point3::new(x, 500.0, 200.0),
+            radius: 1000.0,
+            slope: 0.3,
+            shape: MountainShape::Cone,
+        };
+
+        let terrain = terrain_tree(
+            vec![river],
+            vec![mountain(-500.0), mountain(500.0)],
+            Vec::new(),
+        );
+
+        // the river bed at the center of the valley, between the mountains
+        assert_eq!(terrain.elevation(Point2::new(0.0, 500.0)), 3.0);
+
+        // the valley is blended into the flanks of the mountains
+        let flank = terrain.elevation(Point2::new(80.0, 500.0));
+        assert!(flank > 5.0 && flank < 200.0 - 0.3 * 420.0);
+
+        // the peaks are untouched
+        assert_eq!(terrain.elevation(Point2::new(500.0, 500.0)), 200.0);
+        assert_eq!(terrain.weight(Point2::new(5000.0, 500.0)), 0.0);
+    }
+
+    #[test]
+    fn lakes_carved_into_rivers() {
+        let river = RiverPrimitive {
+            a: Point3::new(0.0, 0.0, 0.0),
+            b: Point3::new(0.0, 1000.0, 10.0),
+            width: 20.0,
+            depth: 2.0,
+            profile: ChannelProfile {
+                cross_section: CrossSection::Flat,
+                valley_width: 10.0,
+                bank_slope: 0.1,
+            },
+        };
+        let lake = LakePrimitive {
+            center: Point3::new(0.0, 1000.0, 10.0),
+            radius: 50.0,
+            depth: 5.0,
+        };
+
+        let terrain = terrain_tree(vec![river], Vec::new(), vec![lake]);
+
+        // the river bed below the lake, then the flat bottom of the lake at its source
+        assert_eq!(terrain.elevation(Point2::new(0.0, 500.0)), 3.0);
+        assert_eq!(terrain.elevation(Point2::new(0.0, 1000.0)), 5.0);
+        assert_eq!(terrain.elevation(Point2::new(20.0, 1000.0)), 5.0);
+        assert!(terrain.weight(Point2::new(0.0, 1090.0)) > 0.0);
+    }
+}
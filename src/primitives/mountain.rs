@@ -0,0 +1,253 @@
+//! Mountain and hill primitives on the vertices of the ridge network.
+
+use petgraph::stable_graph::StableGraph;
+
+use primitives::noise::ridged_noise;
+use primitives::{BoundingBox, Gradient, TerrainPrimitive};
+use river_classifier::{RidgeEdge, RidgeNode};
+use slope_map::SlopeMap;
+use {Point2, Point3, Vector2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MountainShape {
+    /// Falls off at the same slope all the way from the peak.
+    Cone,
+
+    /// A rounded top that gets steeper towards the foot, rolling hills.
+    Dome,
+
+    /// A cone with ridged noise on its flanks, fading out towards the foot.
+    Ridged {
+        /// **Example value:** 200.0
+        height: f64,
+
+        /// Distance between the ridges.
+        ///
+        /// **Example value:** 1000.0
+        wavelength: f64,
+
+        seed: u32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct MountainSettings {
+    /// Distance from the peak at which a mountain stops having an effect.
+    ///
+    /// **Example value:** 3000.0
+    pub radius: f64,
+
+    /// Where the slope map is below this value the mountains are domed hills.
+    ///
+    /// **Example value:** 0.1
+    pub dome_slope: f64,
+
+    /// Where the slope map is at least this value the mountains get ridges.
+    ///
+    /// **Example value:** 0.25
+    pub ridged_slope: f64,
+
+    /// **Example value:** 200.0
+    pub ridge_height: f64,
+
+    /// **Example value:** 1000.0
+    pub ridge_wavelength: f64,
+
+    pub seed: u32,
+}
+
+/// A peak at a ridge vertex, falling off with the slope of the slope map around it, the same
+/// slope the classifier used to raise the vertex above the rivers.
+#[derive(Clone, Debug)]
+pub struct MountainPrimitive {
+    pub peak: Point3,
+    pub radius: f64,
+    pub slope: f64,
+    pub shape: MountainShape,
+}
+
+impl TerrainPrimitive for MountainPrimitive {
+    fn elevation(&self, pos: Point2) -> f64 {
+        let distance = (pos - Point2::new(self.peak.x, self.peak.y)).norm();
+        let cone = self.peak.z - self.slope * distance;
+
+        match self.shape {
+            MountainShape::Cone => cone,
+            MountainShape::Dome => {
+                self.peak.z - self.slope * distance * distance / self.radius.max(distance)
+            }
+            MountainShape::Ridged {
+                height,
+                wavelength,
+                seed,
+            } => {
+                let t = (distance / self.radius).min(1.0);
+                let noise =
+                    ridged_noise(Point2::new(pos.x / wavelength, pos.y / wavelength), seed, 4);
+                cone + height * (noise - 1.0) * t
+            }
+        }
+    }
+
+    fn weight(&self, pos: Point2) -> f64 {
+        let distance = (pos - Point2::new(self.peak.x, self.peak.y)).norm();
+        if distance >= self.radius {
+            return 0.0;
+        }
+
+        let t = distance / self.radius;
+        (1.0 - t * t).powi(2)
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let peak = Point2::new(self.peak.x, self.peak.y);
+        BoundingBox::new(peak, peak).expand(self.radius)
+    }
+
+    /// Analytic for cones and domes, the noise of ridged mountains has none.
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        let offset = pos - Point2::new(self.peak.x, self.peak.y);
+        let distance = offset.norm();
+        let direction = if distance > 0.0 {
+            offset / distance
+        } else {
+            Vector2::zeros()
+        };
+
+        let elevation = match self.shape {
+            MountainShape::Cone => -self.slope,
+            MountainShape::Dome if distance < self.radius => {
+                -2.0 * self.slope * distance / self.radius
+            }
+            MountainShape::Dome => -self.slope,
+            MountainShape::Ridged { .. } => return None,
+        };
+
+        let t = distance / self.radius;
+        let weight = if t < 1.0 {
+            -4.0 * t * (1.0 - t * t) / self.radius
+        } else {
+            0.0
+        };
+
+        Some(Gradient {
+            elevation: direction * elevation,
+            weight: direction * weight,
+        })
+    }
+}
+
+/// A mountain at every vertex of the ridge network. The shape is chosen by the value of the
+/// slope map at the vertex.
+pub fn mountain_primitives<SM: SlopeMap>(
+    ridges: &StableGraph<RidgeNode, RidgeEdge>,
+    slope_map: &SM,
+    settings: &MountainSettings,
+) -> Vec<MountainPrimitive> {
+    ridges
+        .node_indices()
+        .map(|idx| {
+            let peak = ridges[idx].pos;
+            let slope = slope_map.sample(Point2::new(peak.x, peak.y));
+
+            let shape = if slope < settings.dome_slope {
+                MountainShape::Dome
+            } else if slope < settings.ridged_slope {
+                MountainShape::Cone
+            } else {
+                MountainShape::Ridged {
+                    height: settings.ridge_height,
+                    wavelength: settings.ridge_wavelength,
+                    seed: settings.seed.wrapping_add(idx.index() as u32),
+                }
+            };
+
+            MountainPrimitive {
+                peak,
+                radius: settings.radius,
+                slope,
+                shape,
+            }
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use slope_map::ArraySlopeMap;
+    use Vector2;
+
+    fn mountain(shape: MountainShape) -> MountainPrimitive {
+        MountainPrimitive {
+            peak: Point3::new(0.0, 0.0, 100.0),
+            radius: 100.0,
+            slope: 0.5,
+            shape,
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        let cone = mountain(MountainShape::Cone);
+        let dome = mountain(MountainShape::Dome);
+
+        assert_eq!(cone.elevation(Point2::new(0.0, 0.0)), 100.0);
+        assert_eq!(cone.elevation(Point2::new(60.0, 80.0)), 50.0);
+        assert_eq!(dome.elevation(Point2::new(50.0, 0.0)), 87.5);
+        assert_eq!(dome.elevation(Point2::new(0.0, 100.0)), 50.0);
+
+        // ridges only cut into the flanks
+        let ridged = mountain(MountainShape::Ridged {
+            height: 20.0,
+            wavelength: 10.0,
+            seed: 1,
+        });
+        assert_eq!(ridged.elevation(Point2::new(0.0, 0.0)), 100.0);
+        for i in 0..100 {
+            let pos = Point2::new(i as f64, 0.0);
+            assert!(ridged.elevation(pos) <= cone.elevation(pos));
+            assert!(ridged.elevation(pos) >= cone.elevation(pos) - 20.0);
+        }
+
+        assert_eq!(cone.weight(Point2::new(0.0, 0.0)), 1.0);
+        assert_eq!(cone.weight(Point2::new(0.0, 100.0)), 0.0);
+        assert!(cone.weight(Point2::new(0.0, 50.0)) > 0.5);
+    }
+
+    #[test]
+    fn shapes_from_slope_map() {
+        let mut ridges = StableGraph::new();
+        for &x in &[5.0, 15.0, 25.0] {
+            ridges.add_node(RidgeNode {
+                pos: Point3::new(x, 5.0, 10.0),
+            });
+        }
+
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.2, 0.4,
+            0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0,
+        ];
+        let slope_map = ArraySlopeMap::new(data, 3, Vector2::new(0.0, 0.0), 30.0);
+        let settings = MountainSettings {
+            radius: 50.0,
+            dome_slope: 0.1,
+            ridged_slope: 0.25,
+            ridge_height: 5.0,
+            ridge_wavelength: 10.0,
+            seed: 0,
+        };
+
+        let mountains = mountain_primitives(&ridges, &slope_map, &settings);
+        assert_eq!(mountains.len(), 3);
+        assert_eq!(mountains[0].shape, MountainShape::Dome);
+        assert_eq!(mountains[1].shape, MountainShape::Cone);
+        assert_eq!(mountains[1].slope, 0.2);
+        match mountains[2].shape {
+            MountainShape::Ridged { height, .. } => assert_eq!(height, 5.0),
+            shape => panic!("unexpected shape {:?}", shape),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Deterministic value noise for the ridges of mountains.
+
+use Point2;
+
+/// Hashes a lattice point to a value in [-1.0, 1.0].
+fn lattice(x: i64, y: i64, seed: u32) -> f64 {
+    let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ u64::from(seed).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h ^= h >> 33;
+
+    (h >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// Value noise in [-1.0, 1.0] with features about one unit apart.
+pub fn value_noise(pos: Point2, seed: u32) -> f64 {
+    let x0 = pos.x.floor();
+    let y0 = pos.y.floor();
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let tx = smooth(pos.x - x0);
+    let ty = smooth(pos.y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let a = lattice(x0, y0, seed);
+    let b = lattice(x0 + 1, y0, seed);
+    let c = lattice(x0, y0 + 1, seed);
+    let d = lattice(x0 + 1, y0 + 1, seed);
+
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * ty
+}
+
+/// Ridged noise in [0.0, 1.0], sharp crests where the value noise crosses zero.
+pub fn ridged_noise(pos: Point2, seed: u32, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut pos = pos;
+
+    for octave in 0..octaves {
+        let n = 1.0 - value_noise(pos, seed.wrapping_add(octave)).abs();
+        sum += n * n * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        pos = Point2::new(pos.x * 2.0, pos.y * 2.0);
+    }
+
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_range() {
+        for i in 0..1000 {
+            let pos = Point2::new(i as f64 * 0.37 - 100.0, i as f64 * 0.11);
+            let n = value_noise(pos, 1);
+            assert!((-1.0..=1.0).contains(&n));
+
+            let r = ridged_noise(pos, 1, 4);
+            assert!((0.0..=1.0).contains(&r));
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let pos = Point2::new(12.3, -4.5);
+        assert_eq!(value_noise(pos, 7), value_noise(pos, 7));
+        assert!(value_noise(pos, 7) != value_noise(pos, 8));
+
+        // The noise passes through the lattice values.
+        assert_eq!(value_noise(Point2::new(3.0, 4.0), 7), lattice(3, 4, 7));
+    }
+}
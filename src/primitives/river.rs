@@ -0,0 +1,328 @@
+//! River segment primitives, a channel carved along each edge of the river network and the
+//! valley around it.
+
+use petgraph::stable_graph::StableGraph;
+use std::collections::HashMap;
+
+use primitives::{BoundingBox, Gradient, TerrainPrimitive};
+use river_classifier::{RiverEdge, RiverNode, RiverType};
+use {Point2, Point3, Vector2};
+
+/// Shape of the channel across the river.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrossSection {
+    /// Deepest at the center and straight banks, steep mountain streams.
+    V,
+
+    /// A flat bed over the middle half of the channel.
+    Trapezoid,
+
+    /// A rounded bed, meandering rivers.
+    Parabolic,
+
+    /// A flat bed over almost the whole channel, braided and anastomosing rivers.
+    Flat,
+}
+
+impl CrossSection {
+    /// Fraction of the full depth at `t`, where `t` is the distance from the center of the
+    /// channel relative to its half width.
+    pub fn depth(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            CrossSection::V => 1.0 - t,
+            CrossSection::Trapezoid => ((1.0 - t) * 2.0).min(1.0),
+            CrossSection::Parabolic => 1.0 - t * t,
+            CrossSection::Flat => ((1.0 - t) * 10.0).min(1.0),
+        }
+    }
+
+    /// Derivative of `depth` with respect to `t`.
+    fn depth_derivative(&self, t: f64) -> f64 {
+        if !(0.0..=1.0).contains(&t) {
+            return 0.0;
+        }
+
+        match *self {
+            CrossSection::V => -1.0,
+            CrossSection::Trapezoid if t > 0.5 => -2.0,
+            CrossSection::Parabolic => -2.0 * t,
+            CrossSection::Flat if t > 0.9 => -10.0,
+            CrossSection::Trapezoid | CrossSection::Flat => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelProfile {
+    pub cross_section: CrossSection,
+
+    /// Width of the valley as a multiple of the width of the channel.
+    ///
+    /// **Example value:** 10.0
+    pub valley_width: f64,
+
+    /// Slope at which the valley rises from the banks of the channel.
+    ///
+    /// **Example value:** 0.1
+    pub bank_slope: f64,
+}
+
+/// Profiles for each river type, from narrow gorges for the steep types to wide and flat
+/// valleys for the braided and anastomosing types.
+pub fn default_channel_profiles() -> HashMap<RiverType, ChannelProfile> {
+    let profile = |cross_section, valley_width, bank_slope| ChannelProfile {
+        cross_section,
+        valley_width,
+        bank_slope,
+    };
+
+    vec![
+        (RiverType::Aplus, profile(CrossSection::V, 3.0, 0.8)),
+        (RiverType::A, profile(CrossSection::V, 4.0, 0.6)),
+        (RiverType::B, profile(CrossSection::Trapezoid, 6.0, 0.3)),
+        (RiverType::C, profile(CrossSection::Parabolic, 10.0, 0.1)),
+        (RiverType::D, profile(CrossSection::Flat, 8.0, 0.05)),
+        (RiverType::DA, profile(CrossSection::Flat, 12.0, 0.02)),
+        (RiverType::E, profile(CrossSection::Parabolic, 6.0, 0.2)),
+        (RiverType::F, profile(CrossSection::Trapezoid, 4.0, 0.8)),
+        (RiverType::G, profile(CrossSection::V, 3.0, 1.0)),
+    ].into_iter()
+    .collect()
+}
+
+/// The channel of one river edge. The water surface follows the elevations of the nodes.
+#[derive(Clone, Debug)]
+pub struct RiverPrimitive {
+    pub a: Point3,
+    pub b: Point3,
+    pub width: f64,
+    pub depth: f64,
+    pub profile: ChannelProfile,
+}
+
+impl RiverPrimitive {
+    /// Position along the segment in [0.0, 1.0] closest to `pos`, and the distance to it.
+    fn closest(&self, pos: Point2) -> (f64, f64) {
+        let a = Point2::new(self.a.x, self.a.y);
+        let b = Point2::new(self.b.x, self.b.y);
+
+        let length = (b - a).norm_squared();
+        let t = if length > 0.0 {
+            ((pos - a).dot(&(b - a)) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        (t, (pos - (a + (b - a) * t)).norm())
+    }
+
+    /// Gradients of the position along the segment and of the distance to it.
+    fn closest_gradient(&self, pos: Point2) -> (Vector2, Vector2) {
+        let a = Point2::new(self.a.x, self.a.y);
+        let b = Point2::new(self.b.x, self.b.y);
+        let (t, distance) = self.closest(pos);
+
+        let length = (b - a).norm_squared();
+        let along = if length > 0.0 && t > 0.0 && t < 1.0 {
+            (b - a) / length
+        } else {
+            Vector2::zeros()
+        };
+        let across = if distance > 0.0 {
+            (pos - (a + (b - a) * t)) / distance
+        } else {
+            Vector2::zeros()
+        };
+
+        (along, across)
+    }
+
+    fn valley_half_width(&self) -> f64 {
+        self.width * 0.5 * self.profile.valley_width.max(1.0)
+    }
+}
+
+impl TerrainPrimitive for RiverPrimitive {
+    fn elevation(&self, pos: Point2) -> f64 {
+        let (t, distance) = self.closest(pos);
+        let surface = self.a.z + (self.b.z - self.a.z) * t;
+        let half_width = self.width * 0.5;
+
+        if distance < half_width {
+            surface - self.depth * self.profile.cross_section.depth(distance / half_width)
+        } else {
+            surface + (distance - half_width) * self.profile.bank_slope
+        }
+    }
+
+    fn weight(&self, pos: Point2) -> f64 {
+        let (_, distance) = self.closest(pos);
+        let half_width = self.width * 0.5;
+        let valley = self.valley_half_width();
+
+        if distance >= valley {
+            0.0
+        } else if distance <= half_width {
+            1.0
+        } else {
+            let t = (distance - half_width) / (valley - half_width);
+            1.0 - t * t * (3.0 - 2.0 * t)
+        }
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_points(
+            Point2::new(self.a.x, self.a.y),
+            Point2::new(self.b.x, self.b.y),
+        ).expand(self.valley_half_width())
+    }
+
+    fn gradient(&self, pos: Point2) -> Option<Gradient> {
+        let (_, distance) = self.closest(pos);
+        let (along, across) = self.closest_gradient(pos);
+        let half_width = self.width * 0.5;
+        let valley = self.valley_half_width();
+
+        let surface = along * (self.b.z - self.a.z);
+        let elevation = if distance < half_width {
+            let t = distance / half_width;
+            let depth = self.depth * self.profile.cross_section.depth_derivative(t);
+            surface - across * (depth / half_width)
+        } else {
+            surface + across * self.profile.bank_slope
+        };
+
+        let weight = if distance > half_width && distance < valley {
+            let t = (distance - half_width) / (valley - half_width);
+            across * (-6.0 * t * (1.0 - t) / (valley - half_width))
+        } else {
+            Vector2::zeros()
+        };
+
+        Some(Gradient { elevation, weight })
+    }
+}
+
+/// A river primitive for every edge of the classified network, with the profile of its river
+/// type. Edges without a type, or with a type missing from `profiles`, are skipped.
+pub fn river_primitives(
+    graph: &StableGraph<RiverNode, RiverEdge>,
+    profiles: &HashMap<RiverType, ChannelProfile>,
+) -> Vec<RiverPrimitive> {
+    graph
+        .edge_indices()
+        .filter_map(|idx| {
+            let edge = &graph[idx];
+            let profile = edge.rosgen.and_then(|rosgen| profiles.get(&rosgen))?;
+            let (a, b) = graph.edge_endpoints(idx)?;
+
+            Some(RiverPrimitive {
+                a: graph[a].pos,
+                b: graph[b].pos,
+                width: edge.width,
+                depth: edge.depth,
+                profile: *profile,
+            })
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use river_gen::NodeKind;
+
+    fn primitive(cross_section: CrossSection) -> RiverPrimitive {
+        RiverPrimitive {
+            a: Point3::new(0.0, 0.0, 10.0),
+            b: Point3::new(100.0, 0.0, 20.0),
+            width: 10.0,
+            depth: 2.0,
+            profile: ChannelProfile {
+                cross_section,
+                valley_width: 5.0,
+                bank_slope: 0.5,
+            },
+        }
+    }
+
+    #[test]
+    fn channel_and_valley() {
+        let river = primitive(CrossSection::V);
+
+        // the bed at the center of the channel and the water surface at the banks
+        assert_eq!(river.elevation(Point2::new(50.0, 0.0)), 13.0);
+        assert_eq!(river.elevation(Point2::new(50.0, 5.0)), 15.0);
+        assert_eq!(river.elevation(Point2::new(50.0, -2.5)), 14.0);
+
+        // the valley rises from the banks
+        assert_eq!(river.elevation(Point2::new(50.0, 15.0)), 20.0);
+
+        assert_eq!(river.weight(Point2::new(50.0, 4.0)), 1.0);
+        assert_eq!(river.weight(Point2::new(50.0, 15.0)), 0.5);
+        assert_eq!(river.weight(Point2::new(50.0, 25.0)), 0.0);
+        assert_eq!(river.weight(Point2::new(-25.0, 0.0)), 0.0);
+
+        let bb = river.bounding_box();
+        assert_eq!(bb.min, Point2::new(-25.0, -25.0));
+        assert_eq!(bb.max, Point2::new(125.0, 25.0));
+    }
+
+    #[test]
+    fn cross_sections() {
+        assert_eq!(CrossSection::V.depth(0.5), 0.5);
+        assert_eq!(CrossSection::Trapezoid.depth(0.5), 1.0);
+        assert_eq!(CrossSection::Trapezoid.depth(0.75), 0.5);
+        assert_eq!(CrossSection::Parabolic.depth(0.5), 0.75);
+        assert_eq!(CrossSection::Flat.depth(0.8), 1.0);
+
+        for cross_section in &[
+            CrossSection::V,
+            CrossSection::Trapezoid,
+            CrossSection::Parabolic,
+            CrossSection::Flat,
+        ] {
+            assert_eq!(cross_section.depth(0.0), 1.0);
+            assert_eq!(cross_section.depth(1.0), 0.0);
+        }
+
+        let flat = primitive(CrossSection::Flat);
+        assert_eq!(flat.elevation(Point2::new(50.0, 4.0)), 13.0);
+    }
+
+    #[test]
+    fn primitives_from_edges() {
+        let node = |x, z| RiverNode {
+            pos: Point3::new(x, 0.0, z),
+            kind: NodeKind::River,
+            area: 1.0,
+            cell: Vec::new(),
+            drainage_area: 1.0,
+        };
+        let edge = |rosgen| RiverEdge {
+            flow: 1.0,
+            width: 4.0,
+            depth: 1.0,
+            velocity: 0.25,
+            rosgen,
+        };
+
+        let mut graph = StableGraph::new();
+        let mouth = graph.add_node(node(0.0, 0.0));
+        let a = graph.add_node(node(10.0, 1.0));
+        let b = graph.add_node(node(20.0, 2.0));
+        graph.add_edge(mouth, a, edge(Some(RiverType::C)));
+        graph.add_edge(a, b, edge(None));
+
+        let primitives = river_primitives(&graph, &default_channel_profiles());
+        assert_eq!(primitives.len(), 1);
+        assert_eq!(primitives[0].b, Point3::new(10.0, 0.0, 1.0));
+        assert_eq!(primitives[0].profile.cross_section, CrossSection::Parabolic);
+    }
+
+    #[test]
+    fn profile_for_every_type() {
+        assert_eq!(default_channel_profiles().len(), 9);
+    }
+}
@@ -0,0 +1,242 @@
+//! Debug drawings of the Delaunay triangulation and Voronoi diagram built by the classifier.
+//!
+//! The drawings can be shown with gnuplot or written to SVG, which does not need gnuplot.
+
+use petgraph::stable_graph::StableGraph;
+use plot::prelude::*;
+use svg::node::element::path::Data;
+use svg::node::element::{Circle, Group, Path};
+use svg::Document;
+
+use river_classifier::{bounding_box, ClassifierError, RiverClassifier};
+use {river_gen, slope_map::SlopeMap, Point2};
+
+#[derive(Clone, Debug)]
+pub struct DebugDrawing {
+    triangles: Vec<[Point2; 3]>,
+    circumcenters: Vec<Point2>,
+    cells: Vec<Vec<Point2>>,
+    contour: Vec<Point2>,
+}
+
+impl<SM: SlopeMap> RiverClassifier<SM> {
+    /// Draws the triangulation and Voronoi diagram that `generate` builds for `graph`.
+    pub fn debug_drawing(
+        &self,
+        graph: &StableGraph<river_gen::RiverNode, ()>,
+    ) -> Result<DebugDrawing, ClassifierError> {
+        let diagram = self.diagram(graph)?;
+
+        Ok(DebugDrawing {
+            triangles: diagram
+                .triangles
+                .chunks(3)
+                .map(|t| {
+                    [
+                        diagram.points[t[0]],
+                        diagram.points[t[1]],
+                        diagram.points[t[2]],
+                    ]
+                }).collect(),
+            circumcenters: diagram
+                .vertices
+                .iter()
+                .map(|v| Point2::new(v.x, v.y))
+                .collect(),
+            cells: graph
+                .node_indices()
+                .map(|idx| diagram.cells[&idx].clone())
+                .collect(),
+            contour: self.contour.clone(),
+        })
+    }
+}
+
+/// Iterates over the points of a polygon, repeating the first point at the end.
+fn closed(polygon: &[Point2]) -> impl Iterator<Item = &Point2> + Clone {
+    polygon.iter().chain(polygon.iter().take(1))
+}
+
+fn path(polygon: &[Point2]) -> Data {
+    let mut points = polygon.iter();
+    let data = match points.next() {
+        Some(p) => Data::new().move_to((p.x, p.y)),
+        None => return Data::new(),
+    };
+
+    points.fold(data, |d, p| d.line_to((p.x, p.y))).close()
+}
+
+impl DebugDrawing {
+    /// A gnuplot figure of the drawing, call `draw` on it to show it.
+    pub fn figure(&self) -> Figure {
+        let mut figure = Figure::new();
+        figure.configure(Key, |k| {
+            k.set(Boxed::Yes)
+                .set(Position::Inside(Vertical::Top, Horizontal::Left))
+        });
+
+        for triangle in &self.triangles {
+            let points = closed(triangle);
+            figure.plot(
+                LinesPoints {
+                    x: points.clone().map(|p| p.x),
+                    y: points.map(|p| p.y),
+                },
+                |lp| {
+                    lp.set(Color::Black)
+                        .set(LineType::Dash)
+                        .set(PointSize(0.4))
+                        .set(PointType::FilledCircle)
+                },
+            );
+        }
+
+        figure.plot(
+            Points {
+                x: self.circumcenters.iter().map(|p| p.x),
+                y: self.circumcenters.iter().map(|p| p.y),
+            },
+            |p| {
+                p.set(Color::ForestGreen)
+                    .set(PointSize(1.0))
+                    .set(PointType::FilledCircle)
+            },
+        );
+
+        for cell in &self.cells {
+            let points = closed(cell);
+            figure.plot(
+                LinesPoints {
+                    x: points.clone().map(|p| p.x),
+                    y: points.map(|p| p.y),
+                },
+                |lp| {
+                    lp.set(Color::Red)
+                        .set(LineType::Dash)
+                        .set(PointSize(0.4))
+                        .set(PointType::FilledCircle)
+                },
+            );
+        }
+
+        let points = closed(&self.contour);
+        figure.plot(
+            LinesPoints {
+                x: points.clone().map(|p| p.x),
+                y: points.map(|p| p.y),
+            },
+            |lp| {
+                lp.set(Color::Blue)
+                    .set(LineType::Dash)
+                    .set(PointSize(0.4))
+                    .set(PointType::FilledCircle)
+            },
+        );
+
+        figure
+    }
+
+    /// The same drawing as `figure` as an SVG document.
+    pub fn svg(&self) -> Document {
+        let points = self
+            .contour
+            .iter()
+            .chain(self.triangles.iter().flat_map(|t| t.iter()))
+            .cloned();
+        let bb = if self.contour.is_empty() && self.triangles.is_empty() {
+            [Point2::origin(); 4]
+        } else {
+            bounding_box(points, 0.0)
+        };
+        let size = (bb[3] - bb[0]).amax();
+        let margin = size * 0.05;
+        let stroke = size / 500.0;
+
+        let triangles = self.triangles.iter().fold(Group::new(), |g, triangle| {
+            g.add(Path::new().set("d", path(triangle)))
+        });
+
+        let circumcenters = self.circumcenters.iter().fold(Group::new(), |g, p| {
+            g.add(
+                Circle::new()
+                    .set("cx", p.x)
+                    .set("cy", p.y)
+                    .set("r", stroke * 2.0),
+            )
+        });
+
+        let cells = self.cells.iter().fold(Group::new(), |g, cell| {
+            g.add(Path::new().set("d", path(cell)))
+        });
+
+        Document::new()
+            .set(
+                "viewBox",
+                (
+                    bb[0].x - margin,
+                    bb[0].y - margin,
+                    bb[3].x - bb[0].x + margin * 2.0,
+                    bb[3].y - bb[0].y + margin * 2.0,
+                ),
+            ).add(
+                Path::new()
+                    .set("fill", "none")
+                    .set("stroke", "blue")
+                    .set("stroke-width", stroke * 2.0)
+                    .set("d", path(&self.contour)),
+            ).add(
+                triangles
+                    .set("fill", "none")
+                    .set("stroke", "black")
+                    .set("stroke-width", stroke)
+                    .set("stroke-dasharray", stroke * 4.0),
+            ).add(
+                cells
+                    .set("fill", "none")
+                    .set("stroke", "red")
+                    .set("stroke-width", stroke)
+                    .set("stroke-dasharray", stroke * 4.0),
+            ).add(circumcenters.set("fill", "forestgreen"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use petgraph::stable_graph::StableGraph;
+    use river_gen;
+    use slope_map::ArraySlopeMap;
+    use {Point3, Vector2};
+
+    #[test]
+    fn svg_drawing() {
+        let mut graph = StableGraph::new();
+        for &(x, y) in &[(1.0, 1.0), (9.0, 1.0), (5.0, 4.0), (1.0, 9.0), (9.0, 9.0)] {
+            graph.add_node(river_gen::RiverNode {
+                pos: Point3::new(x, y, 0.0),
+                priority: 1,
+                kind: river_gen::NodeKind::River,
+            });
+        }
+
+        let contour = vec![
+            Point2::new(0.0, 0.0),
+            Point2::new(10.0, 0.0),
+            Point2::new(10.0, 10.0),
+            Point2::new(0.0, 10.0),
+        ];
+        let slope_map = ArraySlopeMap::new(vec![0.1], 1, Vector2::new(0.0, 0.0), 10.0);
+        let classifier = RiverClassifier::new(slope_map, contour, Default::default());
+
+        let drawing = classifier.debug_drawing(&graph).unwrap();
+        assert_eq!(drawing.triangles.len(), 12);
+        assert_eq!(drawing.circumcenters.len(), 12);
+        assert_eq!(drawing.cells.len(), 5);
+
+        let svg = drawing.svg().to_string();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<circle").count(), 12);
+    }
+}
@@ -0,0 +1,116 @@
+//! Channel width, depth and velocity from the flow, with the hydraulic geometry relations of
+//! Leopold and Maddock.
+
+use petgraph::stable_graph::StableGraph;
+use std::collections::HashMap;
+
+use river_classifier::{RiverEdge, RiverNode, RiverType};
+
+/// Coefficients of `w = a * Q^b` and `d = c * Q^f`. The velocity follows from `Q = w * d * v`.
+#[derive(Clone, Copy, Debug)]
+pub struct HydraulicGeometry {
+    /// **Example value:** 5.0
+    pub width_coefficient: f64,
+
+    /// **Example value:** 0.5
+    pub width_exponent: f64,
+
+    /// **Example value:** 0.3
+    pub depth_coefficient: f64,
+
+    /// **Example value:** 0.4
+    pub depth_exponent: f64,
+}
+
+impl HydraulicGeometry {
+    pub fn width(&self, flow: f64) -> f64 {
+        self.width_coefficient * flow.powf(self.width_exponent)
+    }
+
+    pub fn depth(&self, flow: f64) -> f64 {
+        self.depth_coefficient * flow.powf(self.depth_exponent)
+    }
+
+    pub fn velocity(&self, flow: f64) -> f64 {
+        let section = self.width(flow) * self.depth(flow);
+        if section > 0.0 {
+            flow / section
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Coefficients for each river type. The steep and entrenched types get narrow and deep
+/// channels, the braided type wide and shallow ones.
+pub fn default_hydraulic_geometry() -> HashMap<RiverType, HydraulicGeometry> {
+    let geometry = |width_coefficient, depth_coefficient| HydraulicGeometry {
+        width_coefficient,
+        width_exponent: 0.5,
+        depth_coefficient,
+        depth_exponent: 0.4,
+    };
+
+    vec![
+        (RiverType::Aplus, geometry(2.0, 0.4)),
+        (RiverType::A, geometry(2.0, 0.4)),
+        (RiverType::B, geometry(4.0, 0.3)),
+        (RiverType::C, geometry(5.0, 0.3)),
+        (RiverType::D, geometry(10.0, 0.12)),
+        (RiverType::DA, geometry(3.0, 0.4)),
+        (RiverType::E, geometry(2.5, 0.45)),
+        (RiverType::F, geometry(6.0, 0.25)),
+        (RiverType::G, geometry(2.0, 0.45)),
+    ].into_iter()
+    .collect()
+}
+
+/// Sets the width, depth and velocity of every classified edge. Edges without a type or with a
+/// type that is missing from `geometry` are left without a channel.
+pub(crate) fn apply_hydraulic_geometry(
+    graph: &mut StableGraph<RiverNode, RiverEdge>,
+    geometry: &HashMap<RiverType, HydraulicGeometry>,
+) {
+    for idx in graph.edge_indices().collect::<Vec<_>>() {
+        let edge = &mut graph[idx];
+        if let Some(geometry) = edge.rosgen.and_then(|rosgen| geometry.get(&rosgen)) {
+            edge.width = geometry.width(edge.flow);
+            edge.depth = geometry.depth(edge.flow);
+            edge.velocity = geometry.velocity(edge.flow);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuity() {
+        let geometry = HydraulicGeometry {
+            width_coefficient: 4.0,
+            width_exponent: 0.5,
+            depth_coefficient: 0.25,
+            depth_exponent: 0.4,
+        };
+
+        assert_eq!(geometry.width(100.0), 40.0);
+        assert!((geometry.depth(100.0) - 0.25 * 100f64.powf(0.4)).abs() < 1e-12);
+
+        let flow = 250.0;
+        let section = geometry.width(flow) * geometry.depth(flow);
+        assert!((section * geometry.velocity(flow) - flow).abs() < 1e-9);
+        assert_eq!(geometry.velocity(0.0), 0.0);
+    }
+
+    #[test]
+    fn geometry_for_every_type() {
+        let geometry = default_hydraulic_geometry();
+        assert_eq!(geometry.len(), 9);
+
+        // braided rivers are wider and shallower than meandering ones
+        let (c, d) = (geometry[&RiverType::C], geometry[&RiverType::D]);
+        assert!(d.width(100.0) > c.width(100.0));
+        assert!(d.depth(100.0) < c.depth(100.0));
+    }
+}
@@ -1,23 +1,60 @@
 use delaunator;
 use na;
+use petgraph::graph::NodeIndex;
 use petgraph::stable_graph::StableGraph;
-use plot::prelude::*;
-use std::{f64, iter};
+use petgraph::visit::DfsPostOrder;
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::{error, f64, fmt, mem};
 
 use {river_gen, slope_map::SlopeMap, Point2, Point3};
 
+#[cfg(feature = "plot")]
+mod debug;
+mod hydraulics;
+mod ridges;
+mod rosgen;
+
+#[cfg(feature = "plot")]
+pub use self::debug::DebugDrawing;
+pub use self::hydraulics::{default_hydraulic_geometry, HydraulicGeometry};
+pub use self::ridges::{RidgeEdge, RidgeNode};
+pub use self::rosgen::{count_river_types, RosgenThresholds};
+
 #[derive(Clone, Debug)]
 pub struct RiverNode {
     pub pos: Point3,
+    pub kind: river_gen::NodeKind,
+
+    /// Area of `cell`.
+    pub area: f64,
+
+    /// The Voronoi cell around the node clipped to the contour, counter-clockwise.
+    pub cell: Vec<Point2>,
+
+    /// Area of the cells of the node and all nodes upstream of it, weighted by the rainfall.
+    pub drainage_area: f64,
 }
 
 #[derive(Clone, Debug)]
 pub struct RiverEdge {
+    /// Flow from the upstream node of the edge, from its drainage area.
     pub flow: f64,
-    pub rosgen: RiverType,
+
+    /// Width of the channel, from the flow and the type of the river.
+    pub width: f64,
+
+    /// Depth of the channel, from the flow and the type of the river.
+    pub depth: f64,
+
+    /// Mean velocity of the water, the flow divided by the cross section of the channel.
+    pub velocity: f64,
+
+    /// `None` until the edge has been classified.
+    pub rosgen: Option<RiverType>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum RiverType {
     Aplus,
     A,
@@ -30,207 +67,299 @@ pub enum RiverType {
     G,
 }
 
+/// The classified river network and the ridges between its drainage cells.
+#[derive(Clone, Debug)]
+pub struct Classification {
+    /// Has the same node and edge indices as the graph grown by `RiverGen`.
+    pub rivers: StableGraph<RiverNode, RiverEdge>,
+
+    pub ridges: StableGraph<RidgeNode, RidgeEdge>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClassifierError {
+    /// The river nodes could not be triangulated.
+    Triangulation,
+}
+
+impl fmt::Display for ClassifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClassifierError::Triangulation => write!(f, "failed to triangulate the river nodes"),
+        }
+    }
+}
+
+impl error::Error for ClassifierError {}
+
+#[derive(Clone, Debug)]
+pub struct RiverClassifierSettings {
+    /// `k` in the flow law `Q = k * A^b`, where `A` is the drainage area in km² and `Q` the flow
+    /// in m³/s, as in Génevaux et al.
+    ///
+    /// **Example value:** 0.42
+    pub flow_coefficient: f64,
+
+    /// `b` in the flow law `Q = k * A^b`.
+    ///
+    /// **Example value:** 0.69
+    pub flow_exponent: f64,
+
+    /// Length of a kilometre in world units, converts the drainage areas for the flow law.
+    ///
+    /// **Example value:** 1000.0
+    pub world_units_per_km: f64,
+
+    pub rosgen: RosgenThresholds,
+
+    /// Width and depth of the channels of each river type.
+    pub hydraulic_geometry: HashMap<RiverType, HydraulicGeometry>,
+}
+
+impl Default for RiverClassifierSettings {
+    fn default() -> RiverClassifierSettings {
+        RiverClassifierSettings {
+            flow_coefficient: 0.42,
+            flow_exponent: 0.69,
+            world_units_per_km: 1000.0,
+            rosgen: RosgenThresholds::default(),
+            hydraulic_geometry: default_hydraulic_geometry(),
+        }
+    }
+}
+
 pub struct RiverClassifier<SM: SlopeMap> {
     slope_map: SM,
     contour: Vec<Point2>,
-
-    pub graph: StableGraph<RiverNode, RiverEdge>,
+    rainfall: Option<Box<dyn Fn(Point2) -> f64>>,
+    settings: RiverClassifierSettings,
 }
 
 impl<SM: SlopeMap> RiverClassifier<SM> {
-    pub fn new(slope_map: SM, contour: Vec<Point2>) -> RiverClassifier<SM> {
+    pub fn new(
+        slope_map: SM,
+        contour: Vec<Point2>,
+        settings: RiverClassifierSettings,
+    ) -> RiverClassifier<SM> {
+        assert!(settings.flow_coefficient >= 0.0);
+        assert!(settings.flow_exponent >= 0.0);
+        assert!(settings.world_units_per_km > 0.0);
+
         RiverClassifier {
             slope_map,
             contour,
-            graph: StableGraph::new(),
+            rainfall: None,
+            settings,
         }
     }
 
-    pub fn generate(&self, graph: &StableGraph<river_gen::RiverNode, ()>) {
-        let points = {
-            let mut points = iter::repeat(delaunator::Point { x: 0., y: 0. })
-                .take(0 + graph.node_count())
-                .collect::<Vec<_>>();
+    /// Sets the rainfall at each position, the area of each cell is weighted by the rainfall at
+    /// its node. Without it the rainfall is 1.0 everywhere.
+    pub fn set_rainfall<F>(&mut self, rainfall: F)
+    where
+        F: Fn(Point2) -> f64 + 'static,
+    {
+        self.rainfall = Some(Box::new(rainfall));
+    }
+
+    /// Classifies the river network grown by `RiverGen` and extracts the ridges around it.
+    pub fn generate(
+        &self,
+        graph: &StableGraph<river_gen::RiverNode, ()>,
+    ) -> Result<Classification, ClassifierError> {
+        let mut diagram = self.diagram(graph)?;
+        let ridges = ridges::ridges(graph, &diagram, &self.contour);
+
+        let mut classified = graph.map(
+            |idx, node| {
+                let cell = diagram.cells.remove(&idx).unwrap();
+                RiverNode {
+                    pos: node.pos,
+                    kind: node.kind,
+                    area: polygon_area(&cell),
+                    cell,
+                    drainage_area: 0.0,
+                }
+            },
+            |_, _| RiverEdge {
+                flow: 0.0,
+                width: 0.0,
+                depth: 0.0,
+                velocity: 0.0,
+                rosgen: None,
+            },
+        );
+
+        self.accumulate_flow(&mut classified);
+        rosgen::classify_edges(&mut classified, &self.slope_map, &self.settings.rosgen);
+        hydraulics::apply_hydraulic_geometry(&mut classified, &self.settings.hydraulic_geometry);
 
-            points.splice(
-                0..,
-                graph.node_indices().map(|idx| {
-                    let node = &graph[idx];
-                    delaunator::Point {
-                        x: node.pos.x,
-                        y: node.pos.y,
-                    }
-                }),
-            );
+        Ok(Classification {
+            rivers: classified,
+            ridges,
+        })
+    }
 
-            let bb = bounding_box(self.contour.iter().cloned(), 2000.);
-            //points.splice(..4, bb.iter().map(|p| delaunator::Point { x: p.x, y: p.y }));
+    /// Sums up the drainage area of every node from the nodes upstream of it, and sets the flow
+    /// of every edge from the drainage area of its upstream node.
+    fn accumulate_flow(&self, graph: &mut StableGraph<RiverNode, RiverEdge>) {
+        let roots = graph
+            .node_indices()
+            .filter(|&idx| {
+                graph
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .next()
+                    .is_none()
+            }).collect::<Vec<_>>();
 
-            points
-        };
+        for root in roots {
+            let mut dfs = DfsPostOrder::new(&*graph, root);
+            while let Some(idx) = dfs.next(&*graph) {
+                let upstream = graph
+                    .neighbors_directed(idx, Direction::Outgoing)
+                    .map(|n| graph[n].drainage_area)
+                    .sum::<f64>();
 
+                let node = &mut graph[idx];
+                let rainfall = match self.rainfall {
+                    Some(ref rainfall) => rainfall(Point2::new(node.pos.x, node.pos.y)),
+                    None => 1.0,
+                };
+                node.drainage_area = node.area * rainfall + upstream;
+            }
+        }
+
+        let km2 = self.settings.world_units_per_km.powi(2);
+        for idx in graph.edge_indices().collect::<Vec<_>>() {
+            let (_, upstream) = graph.edge_endpoints(idx).unwrap();
+            let area = graph[upstream].drainage_area / km2;
+            graph[idx].flow =
+                self.settings.flow_coefficient * area.powf(self.settings.flow_exponent);
+        }
+    }
+
+    /// Builds the Delaunay triangulation of the river nodes and the Voronoi diagram around them.
+    ///
+    /// Four ghost points far outside of the contour are added to the triangulation, so that the
+    /// cells of the nodes on the hull are closed. The ghost points are far enough away that every
+    /// point inside the contour is closer to a river node than to them.
+    fn diagram(
+        &self,
+        graph: &StableGraph<river_gen::RiverNode, ()>,
+    ) -> Result<Diagram, ClassifierError> {
         let graph_indices = graph.node_indices().collect::<Vec<_>>();
 
-        let triangulation = delaunator::triangulate(&points).unwrap();
+        if graph_indices.is_empty() {
+            return Ok(Diagram {
+                #[cfg(feature = "plot")]
+                points: Vec::new(),
+                nodes: graph_indices,
+                triangles: Vec::new(),
+                halfedges: Vec::new(),
+                vertices: Vec::new(),
+                cells: HashMap::new(),
+            });
+        }
+
+        let mut points = graph_indices
+            .iter()
+            .map(|&idx| {
+                let node = &graph[idx];
+                Point2::new(node.pos.x, node.pos.y)
+            }).collect::<Vec<_>>();
 
-        let mut figure = Figure::new();
-        figure.configure(Key, |k| {
-            k.set(Boxed::Yes)
-                .set(Position::Inside(Vertical::Top, Horizontal::Left))
-        });
-        //figure.configure(Axis::BottomX, |a| a.set(Range::Limits(-3000., 13000.)));
-        //figure.configure(Axis::LeftY, |a| a.set(Range::Limits(-3000., 13000.)));
+        let bb = bounding_box(points.iter().chain(&self.contour).cloned(), 0.0);
+        let diagonal = na::distance(&bb[0], &bb[3]);
+        let margin = if diagonal > 0.0 { diagonal } else { 1.0 };
+        points.extend_from_slice(&bounding_box(bb.iter().cloned(), margin));
+
+        let delaunator_points = points
+            .iter()
+            .map(|p| delaunator::Point { x: p.x, y: p.y })
+            .collect::<Vec<_>>();
+        let triangulation =
+            delaunator::triangulate(&delaunator_points).ok_or(ClassifierError::Triangulation)?;
 
         let voronoi_points = triangulation
             .triangles
             .as_slice()
             .chunks(3)
             .map(|indices| {
-                let a = &points[indices[0]];
-                let b = &points[indices[1]];
-                let c = &points[indices[2]];
-
-                let circumcenter = circumcenter(
-                    Point2::new(a.x, a.y),
-                    Point2::new(b.x, b.y),
-                    Point2::new(c.x, c.y),
-                );
-
-                let z = if indices.iter().any(|&i| i < 0) {
-                    0.
-                } else {
-                    let node_a = &graph[graph_indices[indices[0] - 0]];
-                    let node_b = &graph[graph_indices[indices[1] - 0]];
-                    let node_c = &graph[graph_indices[indices[2] - 0]];
-
-                    let max_z = f64::max(node_a.pos.z, f64::max(node_b.pos.z, node_c.pos.z));
-
-                    let distance = na::distance(
-                        &Point2::new(node_a.pos.x, node_a.pos.y),
-                        &Point2::new(circumcenter.x, circumcenter.y),
-                    );
-
-                    max_z
-                        + self
-                            .slope_map
-                            .sample(Point2::new(circumcenter.x, circumcenter.y))
-                            * distance
-                };
+                let circumcenter =
+                    circumcenter(points[indices[0]], points[indices[1]], points[indices[2]]);
+
+                // The elevation only depends on the river nodes, not the ghost points.
+                let nodes = indices
+                    .iter()
+                    .filter(|&&i| i < graph_indices.len())
+                    .map(|&i| &graph[graph_indices[i]])
+                    .collect::<Vec<_>>();
+
+                let z = match nodes.first() {
+                    Some(node) => {
+                        let max_z = nodes.iter().map(|n| n.pos.z).fold(f64::MIN, f64::max);
+                        let distance =
+                            na::distance(&Point2::new(node.pos.x, node.pos.y), &circumcenter);
 
-                figure.plot(
-                    LinesPoints {
-                        x: [a.x, b.x, c.x, a.x].iter(),
-                        y: [a.y, b.y, c.y, a.y].iter(),
-                    },
-                    |lp| {
-                        lp.set(Color::Black)
-                            .set(LineType::Dash)
-                            .set(PointSize(0.4))
-                            .set(PointType::FilledCircle)
-                    },
-                );
-
-                figure.plot(
-                    LinesPoints {
-                        x: iter::once(circumcenter.x),
-                        y: iter::once(circumcenter.y),
-                    },
-                    |d| {
-                        d.set(Color::ForestGreen)
-                            .set(PointSize(1.0))
-                            .set(PointType::FilledCircle)
-                    },
-                );
-
-                println!(
-                    "a {:?}\tb {:?}\tc {:?}\tcc {:?}",
-                    a,
-                    b,
-                    c,
-                    (circumcenter.x, circumcenter.y)
-                );
+                        max_z + self.slope_map.sample(circumcenter) * distance
+                    }
+                    None => 0.0,
+                };
 
                 Point3::new(circumcenter.x, circumcenter.y, z)
             }).collect::<Vec<_>>();
 
-        let areas = points
-            .iter()
-            .enumerate()
-            .map(|(points_index, point)| {
-                let hull_points = triangulation
-                    .triangles
+        let cells = voronoi_cells(&triangulation, points.len())
+            .into_iter()
+            .zip(&graph_indices)
+            .map(|(triangles, &idx)| {
+                let cell = triangles
                     .iter()
-                    .enumerate()
-                    .filter(|(_, &i)| i == points_index)
-                    .map(|(i, _)| i / 3)
-                    .map(|i| voronoi_points[i])
-                    .map(|p| Point2::new(p.x, p.y))
+                    .map(|&t| Point2::new(voronoi_points[t].x, voronoi_points[t].y))
                     .collect::<Vec<_>>();
 
-                let hull_points = graham_scan(hull_points);
-
-                let pairs = hull_points.iter().zip(hull_points.iter().cycle().skip(1));
-                for (a, b) in pairs {
-
+                let mut cell = clip_polygon(&self.contour, &cell);
+                if polygon_area(&cell) < 0.0 {
+                    cell.reverse();
                 }
+                (idx, cell)
+            }).collect();
+
+        Ok(Diagram {
+            #[cfg(feature = "plot")]
+            points,
+            nodes: graph_indices,
+            triangles: triangulation.triangles,
+            halfedges: triangulation.halfedges,
+            vertices: voronoi_points,
+            cells,
+        })
+    }
+}
 
-                let area = 0.5 * hull_points
-                    .iter()
-                    .zip(hull_points.iter().cycle().skip(1))
-                    .map(|(&a, &b)| a.x * b.y - b.x * a.y)
-                    .sum::<f64>();
+/// The Delaunay triangulation of the river nodes and its dual Voronoi diagram.
+struct Diagram {
+    /// Positions of the river nodes, in the order of `nodes`, followed by the four ghost points.
+    #[cfg(feature = "plot")]
+    points: Vec<Point2>,
 
-                println!("{:?}", hull_points.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>());
-
-                figure.plot(
-                    LinesPoints {
-                        x: hull_points
-                            .iter()
-                            .cycle()
-                            .take(hull_points.len() + 1)
-                            .map(|p| p.x),
-                        y: hull_points
-                            .iter()
-                            .cycle()
-                            .take(hull_points.len() + 1)
-                            .map(|p| p.y),
-                    },
-                    |lp| {
-                        lp.set(Color::Red)
-                            .set(LineType::Dash)
-                            .set(PointSize(0.4))
-                            .set(PointType::FilledCircle)
-                    },
-                );
-
-                //hull_points
-                ()
-            }).collect::<Vec<_>>();
+    /// The river node of each point of the triangulation, the ghost points have none.
+    nodes: Vec<NodeIndex>,
 
-        figure.plot(
-            LinesPoints {
-                x: self.contour.iter().cycle().take(self.contour.len() + 1).map(|p| p.x),
-                y: self.contour.iter().cycle().take(self.contour.len() + 1).map(|p| p.y),
-            },
-            |lp| {
-                lp.set(Color::Blue)
-                    .set(LineType::Dash)
-                    .set(PointSize(0.4))
-                    .set(PointType::FilledCircle)
-            },
-        );
+    /// Indices into `points`, three for each triangle.
+    triangles: Vec<usize>,
 
-        figure.draw().unwrap();
+    /// The opposite half-edge of each half-edge in `triangles`, as in `delaunator`.
+    halfedges: Vec<usize>,
 
-        /*
-        println!("points {:?}", voronoi_points);
-        println!("areas {:?}", areas);
-        */
+    /// Circumcenters of the triangles, with the elevation of the ridge between the nodes.
+    vertices: Vec<Point3>,
 
-        panic!();
-    }
+    /// The Voronoi cell around each river node, clipped to the contour.
+    cells: HashMap<NodeIndex, Vec<Point2>>,
 }
 
+/// Intersection of the segment `a` with the infinite line through `b`.
 fn line_intersection(a: (Point2, Point2), b: (Point2, Point2)) -> Option<Point2> {
     let p1 = a.0;
     let p2 = a.1;
@@ -240,13 +369,13 @@ fn line_intersection(a: (Point2, Point2), b: (Point2, Point2)) -> Option<Point2>
     let t_q = (p1.x - p3.x) * (p3.y - p4.y) - (p1.y - p3.y) * (p3.x - p4.x);
     let t_d = (p1.x - p2.x) * (p3.y - p4.y) - (p1.y - p2.y) * (p3.x - p4.x);
 
-    if t_d <= f64::EPSILON * 2. {
+    if t_d.abs() <= f64::EPSILON * 2. {
         return None;
     }
 
     let t = t_q / t_d;
 
-    if t >= 0. && t <= 1.0 {
+    if (0.0..=1.0).contains(&t) {
         let x = p1.x + t * (p2.x - p1.x);
         let y = p1.y + t * (p2.y - p1.y);
         Some(Point2::new(x, y))
@@ -255,6 +384,48 @@ fn line_intersection(a: (Point2, Point2), b: (Point2, Point2)) -> Option<Point2>
     }
 }
 
+/// Signed area of a polygon, positive when the points are counter-clockwise.
+fn polygon_area(polygon: &[Point2]) -> f64 {
+    0.5 * polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum::<f64>()
+}
+
+/// Clips `polygon` against the convex polygon `clip` with the Sutherland-Hodgman algorithm.
+///
+/// `polygon` may be concave. Parts of it that end up separated by the clipping are joined by
+/// edges along the border of `clip`, which enclose no area.
+fn clip_polygon(polygon: &[Point2], clip: &[Point2]) -> Vec<Point2> {
+    if clip.len() < 3 {
+        return Vec::new();
+    }
+
+    let orientation = polygon_area(clip).signum();
+    let mut output = polygon.to_vec();
+
+    for (&a, &b) in clip.iter().zip(clip.iter().cycle().skip(1)) {
+        let inside =
+            |p: Point2| orientation * ((b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)) >= 0.;
+
+        let input = mem::take(&mut output);
+        for (&s, &e) in input.iter().zip(input.iter().cycle().skip(1)) {
+            match (inside(s), inside(e)) {
+                (true, true) => output.push(e),
+                (true, false) => output.extend(line_intersection((s, e), (a, b))),
+                (false, true) => {
+                    output.extend(line_intersection((s, e), (a, b)));
+                    output.push(e);
+                }
+                (false, false) => (),
+            }
+        }
+    }
+
+    output
+}
+
 fn circumcenter(a: Point2, b: Point2, c: Point2) -> Point2 {
     /*
     /// Tries to calculate the inverse slope of the line between two points.
@@ -328,75 +499,52 @@ fn bounding_box<I: Iterator<Item = Point2>>(mut points: I, margin: f64) -> [Poin
     ]
 }
 
-// Based on pseudo code from the Wikipedia article on Graham scan,
-// which is based on the pseudo code from Introduction to algorithms.
-fn graham_scan(mut points: Vec<Point2>) -> Vec<Point2> {
-    if points.len() < 3 {
-        return points;
+/// The triangles around each point of `triangulation` in order, found by walking the half-edges
+/// around the point. The circumcenters of the triangles are the vertices of the point's Voronoi
+/// cell.
+///
+/// The walk around a point on the hull starts and ends at the hull, the cell of such a point is
+/// open and its first and last vertices are the circumcenters of the triangles on the hull.
+/// Points that were left out of the triangulation, such as duplicates, get no triangles.
+fn voronoi_cells(triangulation: &delaunator::Triangulation, point_count: usize) -> Vec<Vec<usize>> {
+    let triangles = &triangulation.triangles;
+    let halfedges = &triangulation.halfedges;
+
+    // A half-edge ending at each point, for points on the hull the one along the hull.
+    let mut incoming = vec![delaunator::EMPTY; point_count];
+    for e in 0..triangles.len() {
+        let end = triangles[delaunator::next_halfedge(e)];
+        if incoming[end] == delaunator::EMPTY || halfedges[e] == delaunator::EMPTY {
+            incoming[end] = e;
+        }
     }
 
-    let lowest_y = points[0].y;
-    let (lowest_i, _lowest_y) =
-        points[1..]
-            .iter()
-            .enumerate()
-            .fold((0, lowest_y), |(lowest_i, lowest_y), (i, p)| {
-                if p.y < lowest_y {
-                    (i + 1, p.y)
-                } else {
-                    (lowest_i, lowest_y)
-                }
-            });
+    incoming
+        .into_iter()
+        .map(|start| {
+            let mut cell = Vec::new();
+            let mut e = start;
 
-    points.swap(0, lowest_i);
+            while e != delaunator::EMPTY {
+                cell.push(e / 3);
 
-    let lowest_point = points[0];
-
-    points.sort_by(|&a, &b| {
-        use std::cmp::Ordering;
-        if na::distance_squared(&a, &lowest_point) < f64::EPSILON * 2. {
-            Ordering::Less
-        } else if na::distance_squared(&b, &lowest_point) < f64::EPSILON * 2. {
-            Ordering::Greater
-        } else {
-            let angle_a = f64::atan2(a.y - lowest_point.y, a.x - lowest_point.x);
-            let angle_b = f64::atan2(b.y - lowest_point.y, b.x - lowest_point.x);
-            angle_a.partial_cmp(&angle_b).unwrap()
-        }
-    });
-
-    fn ccw(p1: Point2, p2: Point2, p3: Point2) -> f64 {
-        (p2.x - p1.x) * (p3.y - p1.y) - (p2.y - p1.y) * (p3.x - p1.x)
-    }
-
-    let mut stack = Vec::with_capacity(points.len());
-    stack.push(points[0]);
-    stack.push(points[1]);
-    stack.push(points[2]);
-
-    for &p in &points[3..] {
-        while stack.len() > 1
-            && ccw(
-                stack[if stack.len() >= 2 { stack.len() - 2 } else { 0 }],
-                stack[stack.len() - 1],
-                p,
-            ) <= 0.
-        {
-            stack.pop();
-        }
-        stack.push(p);
-    }
+                e = halfedges[delaunator::next_halfedge(e)];
+                if e == start {
+                    break;
+                }
+            }
 
-    stack
+            cell
+        }).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use {slope_map::ArraySlopeMap, Point2, Point3, Vector2};
+    use {distance_to_point_squared, pnpoly};
+    use {river_gen, slope_map::ArraySlopeMap, Point2, Point3, Vector2};
 
-    use delaunator;
-    use rand::XorShiftRng;
+    use petgraph::stable_graph::StableGraph;
 
     fn contour(contour: &[(f64, f64)], scale: f64) -> Vec<Point2> {
         contour
@@ -423,43 +571,6 @@ mod tests {
         assert_eq!(bb[3], Point2::new(2., 3.));
     }
 
-    #[test]
-    fn simple_graham_scan() {
-        let points = vec![
-            Point2::new(0., 0.),
-            Point2::new(2., 4.),
-            Point2::new(0., 5.),
-            Point2::new(-2., 4.),
-        ];
-
-        let hull = graham_scan(points.clone());
-
-        assert_eq!(hull, points);
-    }
-
-    #[test]
-    fn excluded_points_graham_scan() {
-        let points = vec![
-            Point2::new(0., 0.),
-            Point2::new(1., 3.),
-            Point2::new(2., 4.),
-            Point2::new(0., 5.),
-            Point2::new(-2., 4.),
-        ];
-
-        let hull = graham_scan(points);
-
-        assert_eq!(
-            hull,
-            vec![
-                Point2::new(0., 0.),
-                Point2::new(2., 4.),
-                Point2::new(0., 5.),
-                Point2::new(-2., 4.),
-            ]
-        );
-    }
-
     #[test]
     fn simple_circumcenter() {
         assert_eq!(
@@ -481,43 +592,48 @@ mod tests {
         );
     }
 
-    #[test]
-    fn classify() {
-        use petgraph::stable_graph::StableGraph;
-        use river_gen;
-
-        const SCALE: f64 = 10_000.0;
-        const CONTOUR: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
-        let contour = contour(CONTOUR, SCALE);
-
+    fn river_graph(scale: f64) -> StableGraph<river_gen::RiverNode, ()> {
         let mut graph = StableGraph::new();
-        let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(0.5, 0.0, 0.0) * SCALE,
+        let mouth = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.5, 0.0, 0.0) * scale,
             priority: 20,
+            kind: river_gen::NodeKind::River,
         });
-        let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(0.5, 0.1, 0.0) * SCALE,
+        let trunk = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.5, 0.1, 0.01) * scale,
             priority: 19,
+            kind: river_gen::NodeKind::River,
         });
-        let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(0.4, 0.2, 0.0) * SCALE,
+        let left = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.4, 0.2, 0.02) * scale,
             priority: 19,
+            kind: river_gen::NodeKind::River,
         });
-        let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(0.6, 0.2, 0.0) * SCALE,
+        let right = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.6, 0.2, 0.03) * scale,
             priority: 19,
+            kind: river_gen::NodeKind::River,
         });
+        graph.add_edge(mouth, trunk, ());
+        graph.add_edge(trunk, left, ());
+        graph.add_edge(trunk, right, ());
 
         let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(0.5, 1.0, 0.0) * SCALE,
+            pos: Point3::new(0.5, 1.0, 0.0) * scale,
             priority: 20,
+            kind: river_gen::NodeKind::River,
         });
         let _node = graph.add_node(river_gen::RiverNode {
-            pos: Point3::new(1.0, 0.5, 0.0) * SCALE,
+            pos: Point3::new(1.0, 0.5, 0.0) * scale,
             priority: 20,
+            kind: river_gen::NodeKind::River,
         });
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
+        graph
+    }
+
+    fn slope_map(scale: f64) -> ArraySlopeMap {
+        #[rustfmt::skip]
         let data = vec![
             0.0, 0.1, 0.1, 0.0,
             0.1, 0.2, 0.1, 0.1,
@@ -525,9 +641,393 @@ mod tests {
             0.1, 0.1, 0.0, 0.0,
         ];
 
-        let slope_map = ArraySlopeMap::new(data, 4, Vector2::new(0.0, 0.0), SCALE);
+        ArraySlopeMap::new(data, 4, Vector2::new(0.0, 0.0), scale)
+    }
+
+    #[test]
+    fn classify() {
+        const SCALE: f64 = 10_000.0;
+        const CONTOUR: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let contour = contour(CONTOUR, SCALE);
+        let graph = river_graph(SCALE);
+
+        let river_classifier = RiverClassifier::new(slope_map(SCALE), contour, Default::default());
+        let classified = river_classifier.generate(&graph).unwrap().rivers;
+
+        assert_eq!(classified.node_count(), graph.node_count());
+        assert_eq!(classified.edge_count(), graph.edge_count());
+
+        for idx in graph.node_indices() {
+            assert_eq!(classified[idx].pos, graph[idx].pos);
+        }
+        for idx in graph.edge_indices() {
+            assert_eq!(classified.edge_endpoints(idx), graph.edge_endpoints(idx));
+        }
+
+        // The trunk node is surrounded by other nodes, so its cell is closed.
+        let trunk = graph.node_indices().nth(1).unwrap();
+        assert!(classified[trunk].area > 0.0);
+
+        // The nodes rise steeply from the mouth.
+        let counts = count_river_types(&classified);
+        assert_eq!(counts.get(&RiverType::Aplus), Some(&2));
+        assert_eq!(counts.get(&RiverType::A), Some(&1));
+        assert_eq!(counts.values().sum::<usize>(), graph.edge_count());
+
+        for idx in classified.edge_indices() {
+            let edge = &classified[idx];
+            assert!(edge.width > 0.0 && edge.depth > 0.0 && edge.velocity > 0.0);
+        }
+    }
+
+    #[test]
+    fn classify_with_removed_nodes() {
+        const SCALE: f64 = 10_000.0;
+        const CONTOUR: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let contour = contour(CONTOUR, SCALE);
+        let mut graph = river_graph(SCALE);
+        graph.remove_node(NodeIndex::new(4));
+
+        let river_classifier = RiverClassifier::new(slope_map(SCALE), contour, Default::default());
+        let classified = river_classifier.generate(&graph).unwrap().rivers;
+
+        // Every node gets its own cell, the nodes on the coast lie on the edge of it.
+        for idx in classified.node_indices() {
+            let node = &classified[idx];
+            let pos = Point2::new(node.pos.x, node.pos.y);
+            let verts = node
+                .cell
+                .iter()
+                .cloned()
+                .zip(node.cell.iter().cloned().cycle().skip(1));
+            assert!(
+                pnpoly(verts.clone(), pos)
+                    || distance_to_point_squared(verts, pos).unwrap() < 1e-6
+            );
+        }
+    }
+
+    #[test]
+    fn extract_ridges() {
+        const SCALE: f64 = 10_000.0;
+        const CONTOUR: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let contour = contour(CONTOUR, SCALE);
+        let graph = river_graph(SCALE);
+
+        let river_classifier =
+            RiverClassifier::new(slope_map(SCALE), contour.clone(), Default::default());
+        let ridges = river_classifier.generate(&graph).unwrap().ridges;
+        assert!(ridges.edge_count() > 0);
+
+        let verts = contour
+            .iter()
+            .cloned()
+            .zip(contour.iter().cloned().cycle().skip(1));
+        for idx in ridges.node_indices() {
+            let pos = Point2::new(ridges[idx].pos.x, ridges[idx].pos.y);
+            assert!(
+                pnpoly(verts.clone(), pos)
+                    || distance_to_point_squared(verts.clone(), pos).unwrap() < 1e-6
+            );
+        }
+
+        for idx in ridges.edge_indices() {
+            let (a, b) = ridges[idx].cells;
+
+            // No river crosses a ridge.
+            assert!(graph.find_edge(a, b).is_none() && graph.find_edge(b, a).is_none());
+
+            // The ridge is above the nodes on both sides of it.
+            let (u, v) = ridges.edge_endpoints(idx).unwrap();
+            for &n in &[a, b] {
+                assert!(ridges[u].pos.z >= graph[n].pos.z);
+                assert!(ridges[v].pos.z >= graph[n].pos.z);
+            }
+        }
+    }
+
+    #[test]
+    fn ridges_to_the_coast() {
+        // Both cells reach the coast, the ridge between them runs across the whole island.
+        let mut graph = StableGraph::new();
+        for &x in &[0.25, 0.75] {
+            graph.add_node(river_gen::RiverNode {
+                pos: Point3::new(x, 0.5, 0.0),
+                priority: 20,
+                kind: river_gen::NodeKind::River,
+            });
+        }
+
+        let contour = contour(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)], 1.0);
+        let river_classifier = RiverClassifier::new(slope_map(1.0), contour, Default::default());
+        let ridges = river_classifier.generate(&graph).unwrap().ridges;
+
+        let mut length = 0.0;
+        for idx in ridges.edge_indices() {
+            let (u, v) = ridges.edge_endpoints(idx).unwrap();
+            let (u, v) = (ridges[u].pos, ridges[v].pos);
+            assert!((u.x - 0.5).abs() < 1e-9 && (v.x - 0.5).abs() < 1e-9);
+            length += (u.y - v.y).abs();
+        }
+        assert!((length - 1.0).abs() < 1e-9);
+
+        for &y in &[0.0, 1.0] {
+            assert!(ridges
+                .node_indices()
+                .any(|idx| (ridges[idx].pos.y - y).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn classify_coast() {
+        const SCALE: f64 = 10_000.0;
+        // A bay cuts into the land from the north, between the left and right nodes.
+        const CONTOUR: &[(f64, f64)] = &[
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (1.0, 1.0),
+            (0.55, 1.0),
+            (0.5, 0.3),
+            (0.45, 1.0),
+            (0.0, 1.0),
+        ];
+        let contour = contour(CONTOUR, SCALE);
+        let graph = river_graph(SCALE);
+
+        let river_classifier =
+            RiverClassifier::new(slope_map(SCALE), contour.clone(), Default::default());
+        let classified = river_classifier.generate(&graph).unwrap().rivers;
+
+        // The cells cover the land exactly.
+        let total_area = classified
+            .node_indices()
+            .map(|idx| classified[idx].area)
+            .sum::<f64>();
+        assert!((total_area - polygon_area(&contour)).abs() < 1e-6 * SCALE * SCALE);
+
+        for idx in classified.node_indices() {
+            let node = &classified[idx];
+            assert!(node.area > 0.0);
+            assert!((polygon_area(&node.cell) - node.area).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn accumulate_flow() {
+        const SCALE: f64 = 10_000.0;
+        const CONTOUR: &[(f64, f64)] = &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let graph = river_graph(SCALE);
+        let settings = RiverClassifierSettings {
+            flow_coefficient: 2.0,
+            flow_exponent: 0.5,
+            world_units_per_km: 100.0,
+            ..Default::default()
+        };
+
+        let mut river_classifier =
+            RiverClassifier::new(slope_map(SCALE), contour(CONTOUR, SCALE), settings);
+        river_classifier.set_rainfall(|p| if p.x < 0.5 * SCALE { 3.0 } else { 1.0 });
+        let classified = river_classifier.generate(&graph).unwrap().rivers;
+
+        let nodes = graph.node_indices().collect::<Vec<_>>();
+        let (mouth, trunk, left, right) = (nodes[0], nodes[1], nodes[2], nodes[3]);
+
+        // Only the left node is in the rain.
+        assert_eq!(classified[left].drainage_area, classified[left].area * 3.0);
+        assert_eq!(classified[right].drainage_area, classified[right].area);
+        assert_eq!(
+            classified[trunk].drainage_area,
+            classified[trunk].area
+                + classified[left].drainage_area
+                + classified[right].drainage_area
+        );
+        assert_eq!(
+            classified[mouth].drainage_area,
+            classified[mouth].area + classified[trunk].drainage_area
+        );
+        assert_eq!(
+            classified[nodes[4]].drainage_area,
+            classified[nodes[4]].area
+        );
+
+        for idx in classified.edge_indices() {
+            let (_, upstream) = classified.edge_endpoints(idx).unwrap();
+            assert_eq!(
+                classified[idx].flow,
+                2.0 * (classified[upstream].drainage_area / 10_000.0).sqrt()
+            );
+        }
+    }
+
+    #[test]
+    fn classify_two_nodes() {
+        let mut graph = StableGraph::new();
+        let a = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.25, 0.5, 0.0),
+            priority: 20,
+            kind: river_gen::NodeKind::River,
+        });
+        let b = graph.add_node(river_gen::RiverNode {
+            pos: Point3::new(0.75, 0.5, 0.0),
+            priority: 20,
+            kind: river_gen::NodeKind::River,
+        });
+
+        let contour = contour(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)], 1.0);
+        let river_classifier = RiverClassifier::new(slope_map(1.0), contour, Default::default());
+        let classified = river_classifier.generate(&graph).unwrap().rivers;
+
+        assert!((classified[a].area - 0.5).abs() < 1e-9);
+        assert!((classified[b].area - 0.5).abs() < 1e-9);
+        assert!(classified[a].cell.iter().all(|p| p.x <= 0.5 + 1e-9));
+    }
+
+    #[test]
+    fn clip_concave_polygon() {
+        // A U shape, clipping it with a square across the gap splits it in two.
+        let polygon = contour(
+            &[
+                (0.0, 0.0),
+                (3.0, 0.0),
+                (3.0, 3.0),
+                (2.0, 3.0),
+                (2.0, 1.0),
+                (1.0, 1.0),
+                (1.0, 3.0),
+                (0.0, 3.0),
+            ],
+            1.0,
+        );
+        let clip = contour(&[(0.5, 2.0), (2.5, 2.0), (2.5, 3.5), (0.5, 3.5)], 1.0);
+
+        let clipped = clip_polygon(&polygon, &clip);
+        assert_eq!(polygon_area(&clipped), 1.0);
 
-        let river_classifier = RiverClassifier::new(slope_map, contour);
-        river_classifier.generate(&graph);
+        // The orientation of the clip polygon does not matter.
+        let reversed = clip.iter().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(polygon_area(&clip_polygon(&polygon, &reversed)), 1.0);
+
+        let outside = contour(&[(5.0, 5.0), (6.0, 5.0), (6.0, 6.0)], 1.0);
+        assert!(clip_polygon(&polygon, &outside).is_empty());
+    }
+
+    #[test]
+    fn intersect_line() {
+        let segment = (Point2::new(0.0, 0.0), Point2::new(2.0, 0.0));
+        assert_eq!(
+            line_intersection(segment, (Point2::new(1.0, 5.0), Point2::new(1.0, 4.0))),
+            Some(Point2::new(1.0, 0.0))
+        );
+        assert_eq!(
+            line_intersection(segment, (Point2::new(1.0, 4.0), Point2::new(1.0, 5.0))),
+            Some(Point2::new(1.0, 0.0))
+        );
+        assert_eq!(
+            line_intersection(segment, (Point2::new(3.0, 4.0), Point2::new(3.0, 5.0))),
+            None
+        );
+        assert_eq!(
+            line_intersection(segment, (Point2::new(0.0, 1.0), Point2::new(1.0, 1.0))),
+            None
+        );
+    }
+
+    fn triangulate(points: &[Point2]) -> delaunator::Triangulation {
+        let points = points
+            .iter()
+            .map(|p| delaunator::Point { x: p.x, y: p.y })
+            .collect::<Vec<_>>();
+
+        delaunator::triangulate(&points).unwrap()
+    }
+
+    fn cell_polygon(
+        points: &[Point2],
+        triangulation: &delaunator::Triangulation,
+        cell: &[usize],
+    ) -> Vec<Point2> {
+        cell.iter()
+            .map(|&t| {
+                let t = &triangulation.triangles[t * 3..t * 3 + 3];
+                circumcenter(points[t[0]], points[t[1]], points[t[2]])
+            }).collect()
+    }
+
+    #[test]
+    fn voronoi_cells_grid() {
+        let points = (0..25)
+            .map(|i| Point2::new((i % 5) as f64, (i / 5) as f64))
+            .collect::<Vec<_>>();
+        let triangulation = triangulate(&points);
+        let cells = voronoi_cells(&triangulation, points.len());
+
+        for (i, cell) in cells.iter().enumerate() {
+            let (x, y) = (i % 5, i / 5);
+
+            // Every triangle around the point is visited once.
+            let mut sorted = cell.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), cell.len());
+            assert_eq!(
+                cell.len(),
+                triangulation.triangles.iter().filter(|&&p| p == i).count()
+            );
+
+            if x == 0 || x == 4 || y == 0 || y == 4 {
+                continue;
+            }
+
+            // The cells of the inner points are unit squares around them, the vertices of the
+            // cells are repeated where four points share a circumcircle.
+            let polygon = cell_polygon(&points, &triangulation, cell);
+            assert!((polygon_area(&polygon).abs() - 1.0).abs() < 1e-9);
+            for p in &polygon {
+                assert!((p.x - x as f64).abs() - 0.5 < 1e-9);
+                assert!((p.y - y as f64).abs() - 0.5 < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn voronoi_cells_random() {
+        use rand::{Rng, SeedableRng, XorShiftRng};
+
+        let mut rng = XorShiftRng::from_seed([7; 16]);
+        let points = (0..200)
+            .map(|_| Point2::new(rng.gen_range(0.0, 100.0), rng.gen_range(0.0, 100.0)))
+            .collect::<Vec<_>>();
+        let triangulation = triangulate(&points);
+        let cells = voronoi_cells(&triangulation, points.len());
+
+        for (i, cell) in cells.iter().enumerate() {
+            assert_eq!(
+                cell.len(),
+                triangulation.triangles.iter().filter(|&&p| p == i).count()
+            );
+
+            let polygon = cell_polygon(&points, &triangulation, cell);
+            let orientation = polygon_area(&polygon).signum();
+
+            // The vertices of each cell are in order, the cell is convex, and the closed cells
+            // contain their points.
+            let on_hull = triangulation.hull.contains(&i);
+            let corners = polygon.len() - if on_hull { 2 } else { 0 };
+            for j in 0..corners {
+                let a = polygon[j];
+                let b = polygon[(j + 1) % polygon.len()];
+                let c = polygon[(j + 2) % polygon.len()];
+                let ccw = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+                assert!(ccw * orientation >= -1e-6);
+            }
+
+            if !on_hull {
+                let verts = polygon
+                    .iter()
+                    .cloned()
+                    .zip(polygon.iter().cloned().cycle().skip(1));
+                assert!(pnpoly(verts, points[i]));
+            }
+        }
     }
 }
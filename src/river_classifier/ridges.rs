@@ -0,0 +1,119 @@
+//! The ridge network, the edges of the Voronoi diagram that separate the drainage cells.
+
+use delaunator;
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableGraph;
+
+use std::cmp::Ordering;
+
+use river_classifier::{line_intersection, Diagram};
+use {pnpoly, river_gen, Point2, Point3};
+
+#[derive(Clone, Debug)]
+pub struct RidgeNode {
+    /// A vertex of the Voronoi diagram, with the elevation of the ridge.
+    pub pos: Point3,
+}
+
+#[derive(Clone, Debug)]
+pub struct RidgeEdge {
+    /// The river nodes whose cells are separated by the edge.
+    pub cells: (NodeIndex, NodeIndex),
+}
+
+/// Builds the ridge graph from the Voronoi edges between the cells of `graph`.
+///
+/// The edges that a river flows across, between the cells of two connected river nodes, are not
+/// ridges. The edges are clipped to the contour, where they cross it a node is added with the
+/// elevation interpolated along the edge.
+pub(crate) fn ridges(
+    graph: &StableGraph<river_gen::RiverNode, ()>,
+    diagram: &Diagram,
+    contour: &[Point2],
+) -> StableGraph<RidgeNode, RidgeEdge> {
+    let mut ridges = StableGraph::new();
+    let mut ridge_indices = vec![None; diagram.vertices.len()];
+    let mut vertex_node = |ridges: &mut StableGraph<RidgeNode, RidgeEdge>, t: usize| {
+        let pos = diagram.vertices[t];
+        *ridge_indices[t].get_or_insert_with(|| ridges.add_node(RidgeNode { pos }))
+    };
+
+    for e in 0..diagram.halfedges.len() {
+        // Every Voronoi edge is crossed by a pair of half-edges, visit it once.
+        let twin = diagram.halfedges[e];
+        if twin == delaunator::EMPTY || twin < e {
+            continue;
+        }
+
+        let a = diagram.triangles[e];
+        let b = diagram.triangles[delaunator::next_halfedge(e)];
+        let (a, b) = match (diagram.nodes.get(a), diagram.nodes.get(b)) {
+            (Some(&a), Some(&b)) => (a, b),
+            _ => continue,
+        };
+
+        if graph.find_edge(a, b).is_some() || graph.find_edge(b, a).is_some() {
+            continue;
+        }
+
+        let (start, end) = (diagram.vertices[e / 3], diagram.vertices[twin / 3]);
+        let segment = (Point2::new(start.x, start.y), Point2::new(end.x, end.y));
+        for (t0, t1) in clip_segment(contour, segment) {
+            let mut node = |ridges: &mut StableGraph<RidgeNode, RidgeEdge>, t: f64| {
+                if t == 0.0 {
+                    vertex_node(ridges, e / 3)
+                } else if t == 1.0 {
+                    vertex_node(ridges, twin / 3)
+                } else {
+                    let pos = start + (end - start) * t;
+                    ridges.add_node(RidgeNode { pos })
+                }
+            };
+
+            let u = node(&mut ridges, t0);
+            let v = node(&mut ridges, t1);
+            ridges.add_edge(u, v, RidgeEdge { cells: (a, b) });
+        }
+    }
+
+    ridges
+}
+
+/// The parts of the segment inside the contour, as ranges of the position along the segment from
+/// 0.0 at its start to 1.0 at its end.
+fn clip_segment(contour: &[Point2], (a, b): (Point2, Point2)) -> Vec<(f64, f64)> {
+    let edges = contour
+        .iter()
+        .cloned()
+        .zip(contour.iter().cloned().cycle().skip(1));
+
+    let length_squared = (b - a).norm_squared();
+    let mut crossings = vec![0.0, 1.0];
+    if length_squared > 0.0 {
+        for (c, d) in edges.clone() {
+            if line_intersection((c, d), (a, b)).is_none() {
+                continue;
+            }
+            if let Some(p) = line_intersection((a, b), (c, d)) {
+                crossings.push((p - a).dot(&(b - a)) / length_squared);
+            }
+        }
+    }
+    crossings.sort_by(|s, t| s.partial_cmp(t).unwrap_or(Ordering::Equal));
+
+    let mut ranges: Vec<(f64, f64)> = Vec::new();
+    for pair in crossings.windows(2) {
+        let (t0, t1) = (pair[0], pair[1]);
+        if t1 <= t0 || !pnpoly(edges.clone(), a + (b - a) * ((t0 + t1) * 0.5)) {
+            continue;
+        }
+
+        // Join the parts that only touch the contour in between.
+        match ranges.last_mut() {
+            Some(last) if last.1 == t0 => last.1 = t1,
+            _ => ranges.push((t0, t1)),
+        }
+    }
+
+    ranges
+}
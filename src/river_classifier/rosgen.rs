@@ -0,0 +1,184 @@
+//! Rosgen classification of the river edges.
+//!
+//! The channel slope alone separates the steep types A+, A and B from the rest, as in the table of
+//! Rosgen types in Génevaux et al. The slope map stands in for how entrenched the channel is, and
+//! the flow for the width of the river.
+
+use na;
+use petgraph::stable_graph::StableGraph;
+use std::collections::HashMap;
+use std::f64;
+
+use river_classifier::{RiverEdge, RiverNode, RiverType};
+use slope_map::SlopeMap;
+use {Point2, Point3};
+
+#[derive(Clone, Debug)]
+pub struct RosgenThresholds {
+    /// Channels at least this steep are A+.
+    ///
+    /// **Example value:** 0.1
+    pub aplus_slope: f64,
+
+    /// Channels at least this steep are A.
+    ///
+    /// **Example value:** 0.04
+    pub a_slope: f64,
+
+    /// Channels at least this steep are B, or G when they are entrenched.
+    ///
+    /// **Example value:** 0.02
+    pub b_slope: f64,
+
+    /// Unconfined channels flatter than this are DA.
+    ///
+    /// **Example value:** 0.005
+    pub da_slope: f64,
+
+    /// Channels where the slope map is at least this value are entrenched, G or F.
+    ///
+    /// **Example value:** 0.25
+    pub entrenched: f64,
+
+    /// Unconfined channels with at least this fraction of the largest flow are braided, D.
+    ///
+    /// **Example value:** 0.5
+    pub braided_flow: f64,
+
+    /// Meandering channels with less than this fraction of the largest flow are narrow, E,
+    /// instead of C.
+    ///
+    /// **Example value:** 0.05
+    pub narrow_flow: f64,
+}
+
+impl Default for RosgenThresholds {
+    fn default() -> RosgenThresholds {
+        RosgenThresholds {
+            aplus_slope: 0.1,
+            a_slope: 0.04,
+            b_slope: 0.02,
+            da_slope: 0.005,
+            entrenched: 0.25,
+            braided_flow: 0.5,
+            narrow_flow: 0.05,
+        }
+    }
+}
+
+impl RosgenThresholds {
+    /// Classifies a channel from its slope, its flow relative to the largest flow in the network,
+    /// and the value of the slope map around it.
+    pub fn classify(&self, slope: f64, relative_flow: f64, terrain: f64) -> RiverType {
+        if slope >= self.aplus_slope {
+            RiverType::Aplus
+        } else if slope >= self.a_slope {
+            RiverType::A
+        } else if terrain >= self.entrenched {
+            if slope >= self.b_slope {
+                RiverType::G
+            } else {
+                RiverType::F
+            }
+        } else if relative_flow >= self.braided_flow {
+            RiverType::D
+        } else if slope >= self.b_slope {
+            RiverType::B
+        } else if slope < self.da_slope {
+            RiverType::DA
+        } else if relative_flow < self.narrow_flow {
+            RiverType::E
+        } else {
+            RiverType::C
+        }
+    }
+}
+
+/// Sets `rosgen` of every edge, the flow of the edges must already be known.
+pub(crate) fn classify_edges<SM: SlopeMap>(
+    graph: &mut StableGraph<RiverNode, RiverEdge>,
+    slope_map: &SM,
+    thresholds: &RosgenThresholds,
+) {
+    let max_flow = graph
+        .edge_indices()
+        .map(|idx| graph[idx].flow)
+        .fold(0.0, f64::max);
+
+    for idx in graph.edge_indices().collect::<Vec<_>>() {
+        let (a, b) = graph.edge_endpoints(idx).unwrap();
+        let slope = channel_slope(graph[a].pos, graph[b].pos);
+        let midpoint = na::center(&graph[a].pos, &graph[b].pos);
+        let terrain = slope_map.sample(Point2::new(midpoint.x, midpoint.y));
+
+        let edge = &mut graph[idx];
+        let relative_flow = if max_flow > 0.0 {
+            edge.flow / max_flow
+        } else {
+            0.0
+        };
+
+        edge.rosgen = Some(thresholds.classify(slope, relative_flow, terrain));
+    }
+}
+
+/// Rise over run from the downstream node `a` to the upstream node `b`.
+fn channel_slope(a: Point3, b: Point3) -> f64 {
+    let run = (Point2::new(b.x, b.y) - Point2::new(a.x, a.y)).norm();
+    if run > 0.0 {
+        ((b.z - a.z) / run).max(0.0)
+    } else {
+        0.0
+    }
+}
+
+/// Number of edges of each Rosgen type, edges that have not been classified are not counted.
+pub fn count_river_types(graph: &StableGraph<RiverNode, RiverEdge>) -> HashMap<RiverType, usize> {
+    let mut counts = HashMap::new();
+    for idx in graph.edge_indices() {
+        if let Some(rosgen) = graph[idx].rosgen {
+            *counts.entry(rosgen).or_insert(0) += 1;
+        }
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_by_slope() {
+        let thresholds = RosgenThresholds::default();
+
+        assert_eq!(thresholds.classify(0.2, 0.1, 0.0), RiverType::Aplus);
+        assert_eq!(thresholds.classify(0.05, 0.1, 0.5), RiverType::A);
+        assert_eq!(thresholds.classify(0.03, 0.1, 0.0), RiverType::B);
+        assert_eq!(thresholds.classify(0.01, 0.1, 0.0), RiverType::C);
+        assert_eq!(thresholds.classify(0.001, 0.1, 0.0), RiverType::DA);
+    }
+
+    #[test]
+    fn classify_by_terrain_and_flow() {
+        let thresholds = RosgenThresholds::default();
+
+        // entrenched
+        assert_eq!(thresholds.classify(0.03, 0.1, 0.3), RiverType::G);
+        assert_eq!(thresholds.classify(0.01, 0.1, 0.3), RiverType::F);
+
+        // braided and narrow
+        assert_eq!(thresholds.classify(0.03, 0.8, 0.0), RiverType::D);
+        assert_eq!(thresholds.classify(0.01, 0.01, 0.0), RiverType::E);
+    }
+
+    #[test]
+    fn slope_of_channel() {
+        let a = Point3::new(0.0, 0.0, 1.0);
+        let b = Point3::new(3.0, 4.0, 1.5);
+
+        assert_eq!(channel_slope(a, b), 0.1);
+        assert_eq!(channel_slope(b, a), 0.0);
+        assert_eq!(channel_slope(a, a), 0.0);
+    }
+}
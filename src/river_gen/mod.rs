@@ -5,7 +5,7 @@ use petgraph::stable_graph::StableGraph;
 use petgraph::Direction;
 
 use slope_map::SlopeMap;
-use {distance_to_point_squared, pnpoly, Point2, Point3};
+use {distance_to_point_squared, pnpoly, Point2, Point3, Vector2};
 
 use std::f64;
 
@@ -15,6 +15,17 @@ const EPSILON: f64 = 0.001;
 pub struct RiverNode {
     pub pos: Point3,
     pub priority: u32,
+    pub kind: NodeKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeKind {
+    /// A point on a river channel.
+    River,
+
+    /// A lake with a flat surface at the elevation of the node. The edge from the parent of the
+    /// node is the outlet of the lake and the edges to its children are inflows.
+    Lake { radius: f64 },
 }
 
 #[derive(Clone, Debug)]
@@ -49,6 +60,24 @@ pub struct RiverGenSettings {
     ///
     /// **Example value:** 1500 = edge_length * (3 / 4)
     pub edge_margin: f64,
+
+    /// Probability that a river ends in a lake where it stalls, because no new node could be
+    /// placed after it.
+    ///
+    /// **Example value:** 0.1
+    pub prob_lake: f64,
+
+    /// New nodes where the slope map is below this value everywhere within `lake_radius` are on a
+    /// plateau and become lakes. Nodes near positions the slope map does not cover never do.
+    ///
+    /// **Example value:** 0.01
+    pub lake_slope: f64,
+
+    /// Radius of the lakes, it should be less than `edge_margin` so that lakes do not flood
+    /// other rivers.
+    ///
+    /// **Example value:** 600.0
+    pub lake_radius: f64,
 }
 
 pub struct RiverGen<Rng: rand::Rng, SM: SlopeMap> {
@@ -81,6 +110,8 @@ impl<Rng: rand::Rng, SM: SlopeMap> RiverGen<Rng, SM> {
         assert!(settings.edge_length > 0.0);
         assert!(settings.edge_margin >= 0.0);
         assert!(settings.edge_margin < settings.edge_length);
+        assert!(settings.prob_lake >= 0.0 && settings.prob_lake <= 1.0);
+        assert!(settings.lake_radius >= 0.0);
 
         let mut candidates = Vec::new();
         for n in graph.node_indices() {
@@ -104,13 +135,13 @@ impl<Rng: rand::Rng, SM: SlopeMap> RiverGen<Rng, SM> {
             }).collect();
 
         RiverGen {
-            rng: rng,
-            slope_map: slope_map,
-            contour: contour,
-            graph: graph,
-            candidates: candidates,
-            edges: edges,
-            settings: settings,
+            rng,
+            slope_map,
+            contour,
+            graph,
+            candidates,
+            edges,
+            settings,
         }
     }
 
@@ -126,37 +157,14 @@ impl<Rng: rand::Rng, SM: SlopeMap> RiverGen<Rng, SM> {
             let idx = self.candidates.iter().position(|&n| n == node_idx).unwrap();
             self.candidates.swap_remove(idx);
 
-            if growth_type - self.settings.prob_growth < 0.0 {
+            let grown = if growth_type - self.settings.prob_growth < 0.0 {
                 // grow
-                if let Some(point) = self.gen_point(node_idx) {
-                    self.add_node(
-                        node_idx,
-                        RiverNode {
-                            pos: point,
-                            priority: priority,
-                        },
-                    );
-                }
+                self.grow(node_idx, priority)
             } else if growth_type - self.settings.prob_growth - self.settings.prob_symmetric < 0.0 {
                 // grow symmetric
-                if let Some(point) = self.gen_point(node_idx) {
-                    self.add_node(
-                        node_idx,
-                        RiverNode {
-                            pos: point,
-                            priority: priority - 1,
-                        },
-                    );
-                }
-                if let Some(point) = self.gen_point(node_idx) {
-                    self.add_node(
-                        node_idx,
-                        RiverNode {
-                            pos: point,
-                            priority: priority - 1,
-                        },
-                    );
-                }
+                let a = self.grow(node_idx, priority - 1);
+                let b = self.grow(node_idx, priority - 1);
+                a || b
             } else if growth_type
                 - self.settings.prob_growth
                 - self.settings.prob_symmetric
@@ -165,30 +173,82 @@ impl<Rng: rand::Rng, SM: SlopeMap> RiverGen<Rng, SM> {
             {
                 // grow asymetric
                 let p = self.rng.gen_range(1, priority);
-                if let Some(point) = self.gen_point(node_idx) {
-                    self.add_node(
-                        node_idx,
-                        RiverNode {
-                            pos: point,
-                            priority: priority,
-                        },
-                    );
-                }
-                if let Some(point) = self.gen_point(node_idx) {
-                    self.add_node(
-                        node_idx,
-                        RiverNode {
-                            pos: point,
-                            priority: p,
-                        },
-                    );
-                }
+                let a = self.grow(node_idx, priority);
+                let b = self.grow(node_idx, p);
+                a || b
             } else {
                 unreachable!();
+            };
+
+            if !grown {
+                self.stall(node_idx);
             }
         }
     }
 
+    /// Tries to grow a new node from `parent_idx`, returns false if there was no room for it.
+    fn grow(&mut self, parent_idx: NodeIndex, priority: u32) -> bool {
+        let pos = match self.gen_point(parent_idx) {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        let kind = if self.on_plateau(Point2::new(pos.x, pos.y)) {
+            NodeKind::Lake {
+                radius: self.settings.lake_radius,
+            }
+        } else {
+            NodeKind::River
+        };
+
+        self.add_node(
+            parent_idx,
+            RiverNode {
+                pos,
+                priority,
+                kind,
+            },
+        );
+
+        true
+    }
+
+    /// Whether a lake fits at `pos`, the slope map is sampled at `pos` and on two rings around it
+    /// out to the radius of a lake.
+    fn on_plateau(&self, pos: Point2) -> bool {
+        const DIRECTIONS: usize = 8;
+
+        let radius = self.settings.lake_radius;
+        let samples = (0..DIRECTIONS).flat_map(|i| {
+            let angle = i as f64 / DIRECTIONS as f64 * f64::consts::PI * 2.0;
+            let direction = Vector2::new(angle.cos(), angle.sin());
+            vec![pos + direction * radius * 0.5, pos + direction * radius]
+        });
+
+        Some(pos).into_iter().chain(samples).all(|p| {
+            self.slope_map.covers(p) && self.slope_map.sample(p) < self.settings.lake_slope
+        })
+    }
+
+    /// A river that could not grow any further may end in a lake.
+    fn stall(&mut self, node_idx: NodeIndex) {
+        let has_parent = self
+            .graph
+            .neighbors_directed(node_idx, Direction::Incoming)
+            .next()
+            .is_some();
+
+        if has_parent
+            && self.graph[node_idx].kind == NodeKind::River
+            && self.settings.prob_lake > 0.0
+            && self.rng.gen::<f64>() < self.settings.prob_lake
+        {
+            self.graph[node_idx].kind = NodeKind::Lake {
+                radius: self.settings.lake_radius,
+            };
+        }
+    }
+
     fn next_node(&self) -> Option<NodeIndex> {
         let lowest = self.candidates.iter().cloned().fold(None, |lowest, node| {
             let z = self.graph[node].pos.z;
@@ -297,7 +357,7 @@ mod tests {
     use super::*;
     use {slope_map::ArraySlopeMap, Point2, Point3, Vector2};
 
-    use rand::XorShiftRng;
+    use rand::{SeedableRng, XorShiftRng};
 
     fn contour(contour: &[(f64, f64)], scale: f64) -> Vec<Point2> {
         contour
@@ -317,14 +377,17 @@ mod tests {
         let _node = graph.add_node(RiverNode {
             pos: Point3::new(0.5, 0.0, 0.0) * SCALE,
             priority: 20,
+            kind: NodeKind::River,
         });
         let _node = graph.add_node(RiverNode {
             pos: Point3::new(0.5, 1.0, 0.0) * SCALE,
             priority: 20,
+            kind: NodeKind::River,
         });
         let _node = graph.add_node(RiverNode {
             pos: Point3::new(1.0, 0.5, 0.0) * SCALE,
             priority: 20,
+            kind: NodeKind::River,
         });
 
         let settings = RiverGenSettings {
@@ -336,9 +399,13 @@ mod tests {
 
             edge_length: 2000.0,
             edge_margin: 1500.0,
+
+            prob_lake: 0.1,
+            lake_slope: 0.01,
+            lake_radius: 600.0,
         };
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
+        #[rustfmt::skip]
         let data = vec![
             0.0, 0.1, 0.1, 0.0,
             0.1, 0.2, 0.1, 0.1,
@@ -349,7 +416,11 @@ mod tests {
         let slope_map = ArraySlopeMap::new(data, 4, Vector2::new(0.0, 0.0), SCALE);
 
         RiverGen::new(
-            XorShiftRng::new_unseeded(),
+            // The state of the deprecated `XorShiftRng::new_unseeded`.
+            XorShiftRng::from_seed([
+                0x54, 0x67, 0x3a, 0x19, 0x69, 0xd4, 0xa7, 0xa8, 0x05, 0x0e, 0x83, 0x97, 0xbb, 0xa7,
+                0x3b, 0x11,
+            ]),
             slope_map,
             contour,
             graph,
@@ -377,6 +448,49 @@ mod tests {
         gen.grow_network();
     }
 
+    #[test]
+    fn river_generator_lakes_on_plateau() {
+        let mut gen = river_generator();
+        gen.settings.prob_lake = 0.0;
+        gen.settings.lake_slope = 1.0;
+        gen.grow_network();
+
+        // every node grown on a plateau is a lake, the initial nodes stay rivers
+        let roots = gen
+            .graph
+            .node_indices()
+            .filter(|&idx| {
+                gen.graph
+                    .neighbors_directed(idx, Direction::Incoming)
+                    .next()
+                    .is_none()
+            }).collect::<Vec<_>>();
+        assert!(gen.graph.node_count() > roots.len());
+        for idx in gen.graph.node_indices() {
+            if roots.contains(&idx) {
+                assert_eq!(gen.graph[idx].kind, NodeKind::River);
+            } else {
+                assert_eq!(gen.graph[idx].kind, NodeKind::Lake { radius: 600.0 });
+            }
+        }
+    }
+
+    #[test]
+    fn river_generator_plateaus() {
+        let mut gen = river_generator();
+        gen.settings.lake_slope = 0.05;
+
+        // the lower left cell of the slope map is flat
+        assert!(gen.on_plateau(Point2::new(1250.0, 1250.0)));
+
+        // close to the steeper cell next to it
+        assert!(!gen.on_plateau(Point2::new(2400.0, 1250.0)));
+
+        // outside of the slope map, where it samples 0.0
+        assert!(!gen.on_plateau(Point2::new(-1250.0, 1250.0)));
+        assert!(!gen.on_plateau(Point2::new(200.0, 1250.0)));
+    }
+
     #[test]
     fn river_generator_validate_point() {
         let mut gen = river_generator();
@@ -386,6 +500,7 @@ mod tests {
             RiverNode {
                 pos: Point3::new(5000.0, 2000.0, 10.0),
                 priority: 10,
+                kind: NodeKind::River,
             },
         );
 
@@ -0,0 +1,450 @@
+use slope_map::SlopeMap;
+use {Point2, Vector2};
+
+fn clamp(val: f64) -> f64 {
+    val.clamp(0.0, 1.0)
+}
+
+/// Gradient of `clamp(val)`, which is flat where `val` is outside of [0.0, 1.0].
+fn clamp_gradient(val: f64, gradient: Vector2) -> Vector2 {
+    if !(0.0..=1.0).contains(&val) {
+        Vector2::zeros()
+    } else {
+        gradient
+    }
+}
+
+/// Multiplies the output of a slope map by a constant factor.
+///
+/// Created by `SlopeMap::scale`.
+#[derive(Clone, Debug)]
+pub struct Scale<SM> {
+    map: SM,
+    factor: f64,
+}
+
+impl<SM> Scale<SM> {
+    pub(crate) fn new(map: SM, factor: f64) -> Scale<SM> {
+        Scale { map, factor }
+    }
+}
+
+impl<SM: SlopeMap> SlopeMap for Scale<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.map.sample(pos) * self.factor)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.map.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let val = self.map.sample(pos) * self.factor;
+        clamp_gradient(val, self.map.gradient(pos) * self.factor)
+    }
+}
+
+/// Adds a constant to the output of a slope map.
+///
+/// Created by `SlopeMap::offset`.
+#[derive(Clone, Debug)]
+pub struct Offset<SM> {
+    map: SM,
+    offset: f64,
+}
+
+impl<SM> Offset<SM> {
+    pub(crate) fn new(map: SM, offset: f64) -> Offset<SM> {
+        Offset { map, offset }
+    }
+}
+
+impl<SM: SlopeMap> SlopeMap for Offset<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.map.sample(pos) + self.offset)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.map.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let val = self.map.sample(pos) + self.offset;
+        clamp_gradient(val, self.map.gradient(pos))
+    }
+}
+
+/// Sum of two slope maps.
+///
+/// Created by `SlopeMap::plus`.
+#[derive(Clone, Debug)]
+pub struct Add<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Add<A, B> {
+    pub(crate) fn new(a: A, b: B) -> Add<A, B> {
+        Add { a, b }
+    }
+}
+
+impl<A: SlopeMap, B: SlopeMap> SlopeMap for Add<A, B> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.a.sample(pos) + self.b.sample(pos))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.a.covers(pos) && self.b.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let val = self.a.sample(pos) + self.b.sample(pos);
+        clamp_gradient(val, self.a.gradient(pos) + self.b.gradient(pos))
+    }
+}
+
+/// Product of two slope maps.
+///
+/// Created by `SlopeMap::multiply`.
+#[derive(Clone, Debug)]
+pub struct Multiply<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Multiply<A, B> {
+    pub(crate) fn new(a: A, b: B) -> Multiply<A, B> {
+        Multiply { a, b }
+    }
+}
+
+impl<A: SlopeMap, B: SlopeMap> SlopeMap for Multiply<A, B> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.a.sample(pos) * self.b.sample(pos))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.a.covers(pos) && self.b.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        self.a.gradient(pos) * self.b.sample(pos) + self.b.gradient(pos) * self.a.sample(pos)
+    }
+}
+
+/// Smallest value of two slope maps.
+///
+/// Created by `SlopeMap::min`.
+#[derive(Clone, Debug)]
+pub struct Min<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Min<A, B> {
+    pub(crate) fn new(a: A, b: B) -> Min<A, B> {
+        Min { a, b }
+    }
+}
+
+impl<A: SlopeMap, B: SlopeMap> SlopeMap for Min<A, B> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.a.sample(pos).min(self.b.sample(pos)))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.a.covers(pos) && self.b.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        if self.a.sample(pos) <= self.b.sample(pos) {
+            self.a.gradient(pos)
+        } else {
+            self.b.gradient(pos)
+        }
+    }
+}
+
+/// Largest value of two slope maps.
+///
+/// Created by `SlopeMap::max`.
+#[derive(Clone, Debug)]
+pub struct Max<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Max<A, B> {
+    pub(crate) fn new(a: A, b: B) -> Max<A, B> {
+        Max { a, b }
+    }
+}
+
+impl<A: SlopeMap, B: SlopeMap> SlopeMap for Max<A, B> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.a.sample(pos).max(self.b.sample(pos)))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.a.covers(pos) && self.b.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        if self.a.sample(pos) >= self.b.sample(pos) {
+            self.a.gradient(pos)
+        } else {
+            self.b.gradient(pos)
+        }
+    }
+}
+
+/// Linear interpolation between two slope maps, where a third slope map is used as the mask.
+/// A mask value of 0.0 selects `a` and 1.0 selects `b`.
+///
+/// Created by `SlopeMap::lerp`.
+#[derive(Clone, Debug)]
+pub struct Lerp<A, B, M> {
+    a: A,
+    b: B,
+    mask: M,
+}
+
+impl<A, B, M> Lerp<A, B, M> {
+    pub(crate) fn new(a: A, b: B, mask: M) -> Lerp<A, B, M> {
+        Lerp { a, b, mask }
+    }
+}
+
+impl<A: SlopeMap, B: SlopeMap, M: SlopeMap> SlopeMap for Lerp<A, B, M> {
+    fn sample(&self, pos: Point2) -> f64 {
+        let t = clamp(self.mask.sample(pos));
+        let a = self.a.sample(pos);
+        let b = self.b.sample(pos);
+
+        clamp(a + (b - a) * t)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.a.covers(pos) && self.b.covers(pos) && self.mask.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let t = self.mask.sample(pos);
+        let a = self.a.sample(pos);
+        let b = self.b.sample(pos);
+
+        let ga = self.a.gradient(pos);
+        let gb = self.b.gradient(pos);
+        let gt = clamp_gradient(t, self.mask.gradient(pos));
+
+        ga + (gb - ga) * clamp(t) + gt * (b - a)
+    }
+}
+
+/// Clamps the output of a slope map to a narrower range.
+///
+/// Created by `SlopeMap::clamp`.
+#[derive(Clone, Debug)]
+pub struct Clamp<SM> {
+    map: SM,
+    min: f64,
+    max: f64,
+}
+
+impl<SM> Clamp<SM> {
+    pub(crate) fn new(map: SM, min: f64, max: f64) -> Clamp<SM> {
+        assert!(min <= max, "min {} > max {}", min, max);
+        Clamp {
+            map,
+            min: clamp(min),
+            max: clamp(max),
+        }
+    }
+}
+
+impl<SM: SlopeMap> SlopeMap for Clamp<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        self.map.sample(pos).max(self.min).min(self.max)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.map.covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let val = self.map.sample(pos);
+        if val < self.min || val > self.max {
+            Vector2::zeros()
+        } else {
+            self.map.gradient(pos)
+        }
+    }
+}
+
+/// Passes the output of a slope map through a curve.
+///
+/// Created by `SlopeMap::remap`.
+#[derive(Clone)]
+pub struct Remap<SM, F> {
+    map: SM,
+    curve: F,
+}
+
+impl<SM, F> Remap<SM, F> {
+    pub(crate) fn new(map: SM, curve: F) -> Remap<SM, F> {
+        Remap { map, curve }
+    }
+}
+
+impl<SM: SlopeMap, F: Fn(f64) -> f64> SlopeMap for Remap<SM, F> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp((self.curve)(self.map.sample(pos)))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.map.covers(pos)
+    }
+}
+
+/// Transforms the input coordinates before sampling a slope map.
+///
+/// Created by `SlopeMap::transform`.
+#[derive(Clone)]
+pub struct Transform<SM, F> {
+    map: SM,
+    transform: F,
+}
+
+impl<SM, F> Transform<SM, F> {
+    pub(crate) fn new(map: SM, transform: F) -> Transform<SM, F> {
+        Transform { map, transform }
+    }
+}
+
+impl<SM: SlopeMap, F: Fn(Point2) -> Point2> SlopeMap for Transform<SM, F> {
+    fn sample(&self, pos: Point2) -> f64 {
+        clamp(self.map.sample((self.transform)(pos)))
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        self.map.covers((self.transform)(pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slope_map::{from_fn, ArraySlopeMap, SlopeMap};
+    use {Point2, Vector2};
+
+    fn constant(val: f64) -> ArraySlopeMap {
+        ArraySlopeMap::new(vec![val], 1, Vector2::new(0.0, 0.0), 10.0)
+    }
+
+    fn gradient() -> ArraySlopeMap {
+        ArraySlopeMap::new(vec![0.0, 1.0, 0.0, 1.0], 2, Vector2::new(0.0, 0.0), 10.0)
+    }
+
+    #[test]
+    fn scale_and_offset() {
+        let pos = Point2::new(5.0, 5.0);
+
+        assert_eq!(constant(0.25).scale(2.0).sample(pos), 0.5);
+        assert_eq!(constant(0.75).scale(2.0).sample(pos), 1.0);
+        assert_eq!(constant(0.25).offset(0.5).sample(pos), 0.75);
+        assert_eq!(constant(0.25).offset(-0.5).sample(pos), 0.0);
+    }
+
+    #[test]
+    fn combine_two_maps() {
+        let pos = Point2::new(5.0, 5.0);
+
+        assert_eq!(constant(0.25).plus(constant(0.5)).sample(pos), 0.75);
+        assert_eq!(constant(0.75).plus(constant(0.5)).sample(pos), 1.0);
+        assert_eq!(constant(0.5).multiply(constant(0.5)).sample(pos), 0.25);
+        assert_eq!(constant(0.25).min(constant(0.5)).sample(pos), 0.25);
+        assert_eq!(constant(0.25).max(constant(0.5)).sample(pos), 0.5);
+    }
+
+    #[test]
+    fn lerp_by_mask() {
+        let map = constant(0.0).lerp(constant(1.0), gradient());
+
+        assert_eq!(map.sample(Point2::new(2.5, 2.5)), 0.0);
+        assert_eq!(map.sample(Point2::new(7.5, 2.5)), 1.0);
+
+        let map = constant(0.2).lerp(constant(0.6), constant(0.5));
+        assert!((map.sample(Point2::new(2.5, 2.5)) - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn clamp_and_remap() {
+        let pos = Point2::new(5.0, 5.0);
+
+        assert_eq!(constant(0.9).clamp(0.1, 0.5).sample(pos), 0.5);
+        assert_eq!(constant(0.0).clamp(0.1, 0.5).sample(pos), 0.1);
+        assert_eq!(constant(0.5).remap(|x| x * x).sample(pos), 0.25);
+        assert_eq!(constant(0.5).remap(|x| x * 4.0).sample(pos), 1.0);
+    }
+
+    #[test]
+    fn transform_coordinates() {
+        let map = gradient().transform(|p| Point2::new(p.y, p.x));
+
+        assert_eq!(map.sample(Point2::new(7.5, 2.5)), 0.0);
+        assert_eq!(map.sample(Point2::new(2.5, 7.5)), 1.0);
+    }
+
+    #[test]
+    fn propagated_gradients() {
+        let pos = Point2::new(5.0, 5.0);
+        let ramp = || from_fn(|p: Point2| (p.x * 0.01).clamp(0.0, 1.0));
+        let close = |a: Vector2, b: Vector2| (a - b).norm() < 1e-9;
+
+        assert!(close(
+            ramp().scale(2.0).gradient(pos),
+            Vector2::new(0.02, 0.0)
+        ));
+        assert!(close(ramp().scale(50.0).gradient(pos), Vector2::zeros()));
+        assert!(close(
+            ramp().offset(0.5).gradient(pos),
+            Vector2::new(0.01, 0.0)
+        ));
+        assert!(close(
+            ramp().plus(ramp()).gradient(pos),
+            Vector2::new(0.02, 0.0)
+        ));
+        assert!(close(
+            ramp().multiply(constant(0.5)).gradient(pos),
+            Vector2::new(0.005, 0.0)
+        ));
+        assert!(close(
+            ramp().min(constant(0.5)).gradient(pos),
+            Vector2::new(0.01, 0.0)
+        ));
+        assert!(close(
+            ramp().max(constant(0.5)).gradient(pos),
+            Vector2::zeros()
+        ));
+        assert!(close(
+            ramp().clamp(0.0, 0.01).gradient(pos),
+            Vector2::zeros()
+        ));
+
+        // Interpolating between 0.0 and 1.0 with the ramp as mask follows the ramp.
+        let map = constant(0.0).lerp(constant(1.0), ramp());
+        assert!(close(map.gradient(pos), Vector2::new(0.01, 0.0)));
+    }
+
+    #[test]
+    fn chained_adapters() {
+        let map = gradient()
+            .multiply(constant(0.5))
+            .plus(constant(0.25))
+            .remap(|x| x * 2.0)
+            .clamp(0.0, 0.9);
+
+        assert_eq!(map.sample(Point2::new(2.5, 2.5)), 0.5);
+        assert_eq!(map.sample(Point2::new(7.5, 2.5)), 0.9);
+    }
+}
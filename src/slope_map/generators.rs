@@ -0,0 +1,270 @@
+//! Builds `ArraySlopeMap`s from contours and painted strokes.
+
+use slope_map::ArraySlopeMap;
+use {distance_to_point_squared, pnpoly, Point2, Vector2};
+
+/// How the strength of a brush decreases with the distance from the stroke.
+#[derive(Clone, Copy, Debug)]
+pub enum Falloff {
+    /// Full strength within `inner_radius` and `(inner_radius / distance)^2` outside of it.
+    /// This is the brush of the slope map editor in the browser, where `inner_radius` is the size
+    /// of one cell.
+    InverseSquare { inner_radius: f64 },
+
+    /// Decreases linearly from full strength on the stroke to zero at the radius.
+    Linear,
+
+    /// Decreases from full strength to zero at the radius along a smoothstep curve.
+    Smooth,
+
+    /// Full strength everywhere within the radius.
+    Constant,
+}
+
+impl Falloff {
+    /// Weight in [0.0, 1.0] at `distance` from the stroke.
+    pub fn weight(&self, distance: f64, radius: f64) -> f64 {
+        if distance >= radius {
+            return 0.0;
+        }
+
+        match *self {
+            Falloff::InverseSquare { inner_radius } => {
+                if distance <= inner_radius {
+                    1.0
+                } else {
+                    (inner_radius / distance).powi(2)
+                }
+            }
+            Falloff::Linear => 1.0 - distance / radius,
+            Falloff::Smooth => {
+                let t = 1.0 - distance / radius;
+                t * t * (3.0 - 2.0 * t)
+            }
+            Falloff::Constant => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    /// Distance from the stroke at which the brush stops having an effect.
+    ///
+    /// **Example value:** 4 cells, as in the browser editor.
+    pub radius: f64,
+
+    /// Amount added to the slope map at full weight, negative values erase.
+    ///
+    /// **Example value:** 0.017
+    pub strength: f64,
+
+    /// Painted values are clamped to [0.0, max].
+    ///
+    /// **Example value:** 0.3
+    pub max: f64,
+
+    pub falloff: Falloff,
+}
+
+/// A polyline painted with a brush. A stroke with a single point is a single dab.
+#[derive(Clone, Debug)]
+pub struct Stroke {
+    pub points: Vec<Point2>,
+    pub brush: Brush,
+}
+
+impl ArraySlopeMap {
+    /// Creates a slope map from the distance to the contour polygon, `falloff` maps the distance
+    /// of each cell inside the contour to a slope. Cells outside the contour are set to 0.0.
+    pub fn from_contour_distance<F>(
+        contour: &[Point2],
+        size: usize,
+        offset: Vector2,
+        scale: f64,
+        falloff: F,
+    ) -> ArraySlopeMap
+    where
+        F: Fn(f64) -> f64,
+    {
+        let mut map = ArraySlopeMap::new(vec![0.0; size * size], size, offset, scale);
+
+        let verts = contour
+            .iter()
+            .cloned()
+            .zip(contour.iter().cloned().cycle().skip(1));
+
+        for idx in 0..map.data.len() {
+            let pos = map.cell_center(idx);
+            if !pnpoly(verts.clone(), pos) {
+                continue;
+            }
+
+            let distance = distance_to_point_squared(verts.clone(), pos)
+                .map(f64::sqrt)
+                .unwrap_or(0.0);
+            map.data[idx] = falloff(distance).clamp(0.0, 1.0);
+        }
+
+        map
+    }
+
+    /// Creates a slope map by painting `strokes` in order onto an empty map.
+    pub fn from_strokes(
+        strokes: &[Stroke],
+        size: usize,
+        offset: Vector2,
+        scale: f64,
+    ) -> ArraySlopeMap {
+        let mut map = ArraySlopeMap::new(vec![0.0; size * size], size, offset, scale);
+        for stroke in strokes {
+            map.paint_stroke(stroke);
+        }
+
+        map
+    }
+
+    /// Paints a single dab at `pos`, the same as one tick of the browser editor's brush.
+    pub fn paint(&mut self, pos: Point2, brush: &Brush) {
+        self.paint_with(brush, |p| (p - pos).norm());
+    }
+
+    /// Paints along the polyline of `stroke`, every cell is painted once with the weight of its
+    /// distance to the closest segment.
+    pub fn paint_stroke(&mut self, stroke: &Stroke) {
+        match stroke.points.len() {
+            0 => (),
+            1 => self.paint(stroke.points[0], &stroke.brush),
+            _ => {
+                let segments = stroke
+                    .points
+                    .iter()
+                    .cloned()
+                    .zip(stroke.points.iter().cloned().skip(1));
+
+                self.paint_with(&stroke.brush, |p| {
+                    distance_to_point_squared(segments.clone(), p)
+                        .map(f64::sqrt)
+                        .unwrap_or(0.0)
+                });
+            }
+        }
+    }
+
+    fn paint_with<F: Fn(Point2) -> f64>(&mut self, brush: &Brush, distance: F) {
+        let max = brush.max.clamp(0.0, 1.0);
+
+        for idx in 0..self.data.len() {
+            let weight = brush
+                .falloff
+                .weight(distance(self.cell_center(idx)), brush.radius);
+            if weight > 0.0 {
+                let val = self.data[idx] + brush.strength * weight;
+                self.data[idx] = val.clamp(0.0, max);
+            }
+        }
+    }
+
+    fn cell_center(&self, idx: usize) -> Point2 {
+        let cell_size = (self.scale * self.size as f64).recip();
+        let x = (idx % self.size) as f64 + 0.5;
+        let y = (idx / self.size) as f64 + 0.5;
+
+        Point2::new(x, y) * cell_size + self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use slope_map::SlopeMap;
+    use {Point2, Vector2};
+
+    fn editor_brush(strength: f64) -> Brush {
+        Brush {
+            radius: 4.0,
+            strength,
+            max: 0.3,
+            falloff: Falloff::InverseSquare { inner_radius: 1.0 },
+        }
+    }
+
+    fn empty_map() -> ArraySlopeMap {
+        ArraySlopeMap::new(vec![0.0; 25 * 25], 25, Vector2::new(0.0, 0.0), 25.0)
+    }
+
+    #[test]
+    fn contour_distance() {
+        let contour = &[
+            Point2::new(0.0, 0.0),
+            Point2::new(8.0, 0.0),
+            Point2::new(8.0, 8.0),
+            Point2::new(0.0, 8.0),
+        ];
+        let map = ArraySlopeMap::from_contour_distance(
+            contour,
+            12,
+            Vector2::new(-2.0, -2.0),
+            12.0,
+            |d| d / 4.0,
+        );
+
+        // outside of the contour
+        assert_eq!(map.sample(Point2::new(-1.0, 4.0)), 0.0);
+
+        // along the coast and inland
+        assert_eq!(map.sample(Point2::new(0.5, 4.0)), 0.125);
+        assert_eq!(map.sample(Point2::new(2.5, 4.0)), 0.625);
+        assert_eq!(map.sample(Point2::new(4.2, 4.0)), 0.875);
+    }
+
+    #[test]
+    fn paint_like_editor() {
+        let mut map = empty_map();
+        map.paint(Point2::new(12.5, 12.5), &editor_brush(0.017));
+
+        assert_eq!(map.sample(Point2::new(12.5, 12.5)), 0.017);
+        assert_eq!(map.sample(Point2::new(13.5, 12.5)), 0.017);
+        assert_eq!(map.sample(Point2::new(14.5, 12.5)), 0.017 / 4.0);
+        assert_eq!(map.sample(Point2::new(16.5, 12.5)), 0.0);
+
+        // erasing and clamping
+        map.paint(Point2::new(12.5, 12.5), &editor_brush(-1.0));
+        assert_eq!(map.sample(Point2::new(12.5, 12.5)), 0.0);
+        map.paint(Point2::new(12.5, 12.5), &editor_brush(1.0));
+        assert_eq!(map.sample(Point2::new(12.5, 12.5)), 0.3);
+    }
+
+    #[test]
+    fn paint_polyline() {
+        let brush = Brush {
+            radius: 2.0,
+            strength: 0.5,
+            max: 1.0,
+            falloff: Falloff::Linear,
+        };
+        let stroke = Stroke {
+            points: vec![
+                Point2::new(2.5, 2.5),
+                Point2::new(20.5, 2.5),
+                Point2::new(20.5, 20.5),
+            ],
+            brush,
+        };
+        let map = ArraySlopeMap::from_strokes(&[stroke], 25, Vector2::new(0.0, 0.0), 25.0);
+
+        assert_eq!(map.sample(Point2::new(10.5, 2.5)), 0.5);
+        assert_eq!(map.sample(Point2::new(20.5, 10.5)), 0.5);
+        assert_eq!(map.sample(Point2::new(10.5, 3.5)), 0.25);
+        assert_eq!(map.sample(Point2::new(10.5, 10.5)), 0.0);
+    }
+
+    #[test]
+    fn falloff_curves() {
+        assert_eq!(Falloff::Constant.weight(1.9, 2.0), 1.0);
+        assert_eq!(Falloff::Linear.weight(1.0, 2.0), 0.5);
+        assert_eq!(Falloff::Smooth.weight(1.0, 2.0), 0.5);
+        assert_eq!(Falloff::Smooth.weight(0.0, 2.0), 1.0);
+        assert_eq!(Falloff::Constant.weight(2.0, 2.0), 0.0);
+    }
+}
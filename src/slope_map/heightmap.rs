@@ -0,0 +1,300 @@
+use std::f64;
+
+use slope_map::SlopeMap;
+use {Point2, Vector2};
+
+/// How gradient magnitudes (rise over run) are mapped into the range [0.0, 1.0].
+#[derive(Clone, Debug)]
+pub enum SlopeMapping {
+    /// Divides the gradient by `max_gradient` and clamps the result.
+    Linear { max_gradient: f64 },
+
+    /// Divides the gradient by the steepest gradient found in the heightmap.
+    Normalized,
+
+    /// Uses the slope angle, where 0.0 is flat and 1.0 is vertical.
+    Angle,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeightmapSettings {
+    /// Radius, in cells, of the box blur applied to the heights before computing the gradient.
+    /// Zero disables smoothing.
+    ///
+    /// **Example value:** 1
+    pub smoothing: usize,
+
+    /// Mapping from gradient magnitude to slope map values.
+    ///
+    /// **Example value:** `SlopeMapping::Linear { max_gradient: 1.0 }`
+    pub mapping: SlopeMapping,
+}
+
+/// A slope map derived from the gradient magnitude of a height grid, such as a DEM.
+///
+/// Heights are stored row by row, cell `(x, y)` covers the square starting at
+/// `offset + (x, y) * cell_size`. Samples are bilinearly interpolated between cell centers.
+#[derive(Clone, Debug)]
+pub struct HeightmapSlopeMap {
+    data: Vec<f64>,
+    width: usize,
+    height: usize,
+
+    offset: Vector2,
+    cell_size: f64,
+}
+
+impl HeightmapSlopeMap {
+    pub fn new(
+        heights: &[f64],
+        width: usize,
+        height: usize,
+        offset: Vector2,
+        cell_size: f64,
+        settings: HeightmapSettings,
+    ) -> HeightmapSlopeMap {
+        assert_eq!(width * height, heights.len());
+        assert!(width > 0 && height > 0);
+        assert!(cell_size > 0.0);
+
+        let heights = box_blur(heights, width, height, settings.smoothing);
+        let gradients = gradient_magnitudes(&heights, width, height, cell_size);
+
+        let data = match settings.mapping {
+            SlopeMapping::Linear { max_gradient } => {
+                assert!(max_gradient > 0.0);
+                gradients
+                    .iter()
+                    .map(|g| (g / max_gradient).min(1.0))
+                    .collect()
+            }
+            SlopeMapping::Normalized => {
+                let max_gradient = gradients.iter().cloned().fold(0.0, f64::max);
+                if max_gradient > 0.0 {
+                    gradients.iter().map(|g| g / max_gradient).collect()
+                } else {
+                    gradients
+                }
+            }
+            SlopeMapping::Angle => gradients
+                .iter()
+                .map(|g| g.atan() / f64::consts::FRAC_PI_2)
+                .collect(),
+        };
+
+        HeightmapSlopeMap {
+            data,
+            width,
+            height,
+            offset,
+            cell_size,
+        }
+    }
+
+    fn get(&self, x: isize, y: isize) -> f64 {
+        let x = x.max(0).min(self.width as isize - 1) as usize;
+        let y = y.max(0).min(self.height as isize - 1) as usize;
+        self.data[x + y * self.width]
+    }
+}
+
+impl SlopeMap for HeightmapSlopeMap {
+    fn sample(&self, pos: Point2) -> f64 {
+        let pos = (pos - self.offset) / self.cell_size;
+
+        if pos.x < 0.0 || pos.x >= self.width as f64 || pos.y < 0.0 || pos.y >= self.height as f64 {
+            return 0.0;
+        }
+
+        // Cell centers are at half integers.
+        let x = pos.x - 0.5;
+        let y = pos.y - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let x0 = x0 as isize;
+        let y0 = y0 as isize;
+
+        let top = self.get(x0, y0) * (1.0 - tx) + self.get(x0 + 1, y0) * tx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - tx) + self.get(x0 + 1, y0 + 1) * tx;
+        let val = top * (1.0 - ty) + bottom * ty;
+
+        val.clamp(0.0, 1.0)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        let pos = (pos - self.offset) / self.cell_size;
+        pos.x >= 0.0 && pos.x < self.width as f64 && pos.y >= 0.0 && pos.y < self.height as f64
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let pos = (pos - self.offset) / self.cell_size;
+
+        if pos.x < 0.0 || pos.x >= self.width as f64 || pos.y < 0.0 || pos.y >= self.height as f64 {
+            return Vector2::zeros();
+        }
+
+        // Derivative of the bilinear interpolation in `sample`.
+        let x = pos.x - 0.5;
+        let y = pos.y - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let x0 = x0 as isize;
+        let y0 = y0 as isize;
+
+        let v00 = self.get(x0, y0);
+        let v10 = self.get(x0 + 1, y0);
+        let v01 = self.get(x0, y0 + 1);
+        let v11 = self.get(x0 + 1, y0 + 1);
+
+        let dx = (v10 - v00) * (1.0 - ty) + (v11 - v01) * ty;
+        let dy = (v01 - v00) * (1.0 - tx) + (v11 - v10) * tx;
+
+        Vector2::new(dx, dy) / self.cell_size
+    }
+}
+
+/// Separable box blur with clamped edges.
+fn box_blur(data: &[f64], width: usize, height: usize, radius: usize) -> Vec<f64> {
+    if radius == 0 {
+        return data.to_vec();
+    }
+
+    let radius = radius as isize;
+    let blur = |get: &dyn Fn(isize) -> f64, len: usize, i: isize| {
+        let sum = (-radius..=radius)
+            .map(|d| get((i + d).max(0).min(len as isize - 1)))
+            .sum::<f64>();
+        sum / (2 * radius + 1) as f64
+    };
+
+    let mut horizontal = vec![0.0; data.len()];
+    for y in 0..height {
+        let row = &data[y * width..(y + 1) * width];
+        for x in 0..width {
+            horizontal[x + y * width] = blur(&|i| row[i as usize], width, x as isize);
+        }
+    }
+
+    let mut vertical = vec![0.0; data.len()];
+    for x in 0..width {
+        for y in 0..height {
+            vertical[x + y * width] =
+                blur(&|i| horizontal[x + i as usize * width], height, y as isize);
+        }
+    }
+
+    vertical
+}
+
+/// Gradient magnitude using central differences, one sided along the edges.
+fn gradient_magnitudes(data: &[f64], width: usize, height: usize, cell_size: f64) -> Vec<f64> {
+    let get = |x: usize, y: usize| data[x + y * width];
+
+    let mut gradients = Vec::with_capacity(data.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(height - 1));
+
+            let dx = if x1 > x0 {
+                (get(x1, y) - get(x0, y)) / ((x1 - x0) as f64 * cell_size)
+            } else {
+                0.0
+            };
+            let dy = if y1 > y0 {
+                (get(x, y1) - get(x, y0)) / ((y1 - y0) as f64 * cell_size)
+            } else {
+                0.0
+            };
+
+            gradients.push((dx * dx + dy * dy).sqrt());
+        }
+    }
+
+    gradients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use {Point2, Vector2};
+
+    fn plane(gradient: f64) -> Vec<f64> {
+        (0..16).map(|i| (i % 4) as f64 * 10.0 * gradient).collect()
+    }
+
+    fn heightmap(heights: &[f64], smoothing: usize, mapping: SlopeMapping) -> HeightmapSlopeMap {
+        let settings = HeightmapSettings { smoothing, mapping };
+        HeightmapSlopeMap::new(heights, 4, 4, Vector2::new(100.0, 0.0), 10.0, settings)
+    }
+
+    #[test]
+    fn flat_heightmap() {
+        let map = heightmap(&[5.0; 16], 1, SlopeMapping::Normalized);
+
+        assert_eq!(map.sample(Point2::new(120.0, 20.0)), 0.0);
+    }
+
+    #[test]
+    fn sloped_plane() {
+        let map = heightmap(&plane(0.5), 0, SlopeMapping::Linear { max_gradient: 1.0 });
+
+        // outside the heightmap
+        assert_eq!(map.sample(Point2::new(0.0, 0.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(141.0, 20.0)), 0.0);
+
+        // on the heightmap
+        assert!((map.sample(Point2::new(100.0, 0.0)) - 0.5).abs() < 1e-9);
+        assert!((map.sample(Point2::new(123.0, 17.0)) - 0.5).abs() < 1e-9);
+
+        let map = heightmap(&plane(1.0), 0, SlopeMapping::Angle);
+        assert!((map.sample(Point2::new(123.0, 17.0)) - 0.5).abs() < 1e-9);
+
+        let map = heightmap(&plane(4.0), 0, SlopeMapping::Linear { max_gradient: 1.0 });
+        assert_eq!(map.sample(Point2::new(123.0, 17.0)), 1.0);
+    }
+
+    #[test]
+    fn bilinear_gradient() {
+        #[rustfmt::skip]
+        let heights = vec![
+            0.0, 0.0, 0.0, 10.0,
+            0.0, 0.0, 0.0, 10.0,
+            0.0, 0.0, 0.0, 10.0,
+            0.0, 0.0, 0.0, 10.0,
+        ];
+        let map = heightmap(&heights, 0, SlopeMapping::Linear { max_gradient: 1.0 });
+
+        // Slope values are 0.0, 0.0, 0.5 and 1.0 for each column, between the centers of the
+        // last two columns the slope map rises by 0.5 over 10.0.
+        assert!((map.gradient(Point2::new(130.0, 20.0)) - Vector2::new(0.05, 0.0)).norm() < 1e-9);
+        assert_eq!(map.gradient(Point2::new(110.0, 20.0)), Vector2::zeros());
+        assert_eq!(map.gradient(Point2::new(0.0, 0.0)), Vector2::zeros());
+    }
+
+    #[test]
+    fn smoothed_step() {
+        #[rustfmt::skip]
+        let heights = vec![
+            0.0, 0.0, 10.0, 10.0,
+            0.0, 0.0, 10.0, 10.0,
+            0.0, 0.0, 10.0, 10.0,
+            0.0, 0.0, 10.0, 10.0,
+        ];
+
+        let mapping = || SlopeMapping::Linear { max_gradient: 1.0 };
+        let sharp = heightmap(&heights, 0, mapping());
+        let smooth = heightmap(&heights, 1, mapping());
+
+        // The smoothed step spreads the slope out over more cells.
+        assert_eq!(sharp.sample(Point2::new(105.0, 15.0)), 0.0);
+        assert!((sharp.sample(Point2::new(115.0, 15.0)) - 0.5).abs() < 1e-9);
+        assert!((smooth.sample(Point2::new(105.0, 15.0)) - 1.0 / 3.0).abs() < 1e-9);
+        assert!((smooth.sample(Point2::new(115.0, 15.0)) - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
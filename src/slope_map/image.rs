@@ -0,0 +1,564 @@
+//! Reading and writing `ArraySlopeMap`s as grayscale PGM and PNG images.
+//!
+//! Black is a slope of 0.0 and white is a slope of 1.0. The placement of the map in the world is
+//! stored in a [world file](https://en.wikipedia.org/wiki/World_file) next to the image, named
+//! after the image with a `w` appended to the extension, e.g. `slope.png` and `slope.pngw`.
+//! Images whose world file has a negative row size, north-up like most rasters, are flipped when
+//! loaded.
+
+use png;
+use png::HasParameters;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use slope_map::ArraySlopeMap;
+use Vector2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Netpbm grayscale, both binary (P5) and plain (P2) images can be read, binary is written.
+    Pgm,
+    Png,
+}
+
+impl ImageFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "pgm" => Some(ImageFormat::Pgm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn (::std::error::Error) + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl ArraySlopeMap {
+    /// Reads a square grayscale image, `offset` and `scale` are the same as for `new`.
+    pub fn read_image<R: Read>(
+        reader: R,
+        format: ImageFormat,
+        offset: Vector2,
+        scale: f64,
+    ) -> io::Result<ArraySlopeMap> {
+        let (width, height, data) = match format {
+            ImageFormat::Pgm => read_pgm(BufReader::new(reader))?,
+            ImageFormat::Png => read_png(reader)?,
+        };
+
+        if width != height {
+            return Err(invalid_data(format!(
+                "slope map images must be square, found {}x{}",
+                width, height
+            )));
+        }
+        if width == 0 {
+            return Err(invalid_data("slope map images must not be empty"));
+        }
+
+        Ok(ArraySlopeMap::new(data, width, offset, scale))
+    }
+
+    /// Writes the slope map as a grayscale image.
+    pub fn write_image<W: Write>(
+        &self,
+        writer: W,
+        format: ImageFormat,
+        depth: BitDepth,
+    ) -> io::Result<()> {
+        match format {
+            ImageFormat::Pgm => write_pgm(BufWriter::new(writer), self, depth),
+            ImageFormat::Png => write_png(writer, self, depth),
+        }
+    }
+
+    /// Opens an image together with its world file, the format is chosen by the extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ArraySlopeMap> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| invalid_data(format!("unknown image format {}", path.display())))?;
+
+        let world_file = BufReader::new(File::open(world_file_path(path))?);
+        let world_file = read_world_file(world_file)?;
+
+        let image = ArraySlopeMap::read_image(File::open(path)?, format, world_file.corner, 1.0)?;
+        let (size, scale) = (image.size, world_file.pixel_size * image.size as f64);
+
+        let (mut data, mut offset) = (image.data, world_file.corner);
+        if world_file.north_up {
+            // The first row of a north-up image is its northern edge, the slope map starts with
+            // the southern row.
+            data = data
+                .chunks(size)
+                .rev()
+                .flat_map(|row| row.iter().cloned())
+                .collect();
+            offset.y -= scale;
+        }
+
+        Ok(ArraySlopeMap::new(data, size, offset, scale))
+    }
+
+    /// Saves the slope map as an image together with its world file, the format is chosen by the
+    /// extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P, depth: BitDepth) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| invalid_data(format!("unknown image format {}", path.display())))?;
+
+        self.write_image(File::create(path)?, format, depth)?;
+
+        let pixel_size = self.scale.recip() / self.size as f64;
+        let world_file = BufWriter::new(File::create(world_file_path(path))?);
+        write_world_file(world_file, pixel_size, self.offset)
+    }
+}
+
+/// `slope.png` -> `slope.pngw`
+fn world_file_path(path: &Path) -> PathBuf {
+    let mut extension = path
+        .extension()
+        .map(|e| e.to_os_string())
+        .unwrap_or_default();
+    extension.push("w");
+    path.with_extension(extension)
+}
+
+struct WorldFile {
+    pixel_size: f64,
+    /// Corner of the first pixel of the image.
+    corner: Vector2,
+    /// Whether the rows of the image go towards negative y, as in most world files.
+    north_up: bool,
+}
+
+fn read_world_file<R: BufRead>(reader: R) -> io::Result<WorldFile> {
+    let mut values = Vec::with_capacity(6);
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let value = line
+            .parse::<f64>()
+            .map_err(|_| invalid_data(format!("invalid world file value {:?}", line)))?;
+        values.push(value);
+    }
+
+    match values[..] {
+        [a, d, b, e, c, f] => {
+            if d != 0.0 || b != 0.0 || a != e.abs() || a <= 0.0 {
+                return Err(invalid_data(
+                    "only world files with square, unrotated pixels are supported",
+                ));
+            }
+
+            // The world file points at the center of the first pixel.
+            Ok(WorldFile {
+                pixel_size: a,
+                corner: Vector2::new(c - a * 0.5, f - e * 0.5),
+                north_up: e < 0.0,
+            })
+        }
+        _ => Err(invalid_data("world files must contain six values")),
+    }
+}
+
+fn write_world_file<W: Write>(mut writer: W, pixel_size: f64, corner: Vector2) -> io::Result<()> {
+    writeln!(writer, "{}", pixel_size)?;
+    writeln!(writer, "0")?;
+    writeln!(writer, "0")?;
+    writeln!(writer, "{}", pixel_size)?;
+    writeln!(writer, "{}", corner.x + pixel_size * 0.5)?;
+    writeln!(writer, "{}", corner.y + pixel_size * 0.5)?;
+    writer.flush()
+}
+
+fn read_png<R: Read>(reader: R) -> io::Result<(usize, usize, Vec<f64>)> {
+    let mut decoder = png::Decoder::new(reader);
+    // Keep 16 bit samples, the default strips them down to 8 bits.
+    decoder.set(png::Transformations::EXPAND);
+
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        color_type => {
+            return Err(invalid_data(format!(
+                "slope map images must be grayscale, found {:?}",
+                color_type
+            )))
+        }
+    };
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+
+    // `OutputInfo` reports 8 bits for any expanded image, but expansion leaves 16 bit samples
+    // as they are.
+    let data = match reader.info().bit_depth {
+        png::BitDepth::Sixteen => buf
+            .chunks(2 * channels)
+            .map(|c| f64::from(u16::from(c[0]) << 8 | u16::from(c[1])) / f64::from(u16::MAX))
+            .collect(),
+        _ => buf
+            .chunks(channels)
+            .map(|c| f64::from(c[0]) / f64::from(u8::MAX))
+            .collect(),
+    };
+
+    Ok((width, height, data))
+}
+
+fn write_png<W: Write>(writer: W, map: &ArraySlopeMap, depth: BitDepth) -> io::Result<()> {
+    encode_png(
+        writer,
+        map.size,
+        map.size,
+        png::ColorType::Grayscale,
+        map.data.iter().cloned(),
+        depth,
+    )
+}
+
+/// Writes a PNG from samples in [0.0, 1.0], one per channel, row by row. Values outside the range
+/// are clamped and NaN is written as 0.
+pub(crate) fn encode_png<W, I>(
+    writer: W,
+    width: usize,
+    height: usize,
+    color_type: png::ColorType,
+    values: I,
+    depth: BitDepth,
+) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = f64>,
+{
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set(color_type);
+
+    let data = match depth {
+        BitDepth::Eight => {
+            encoder.set(png::BitDepth::Eight);
+            values
+                .into_iter()
+                .map(|v| quantize(v, 0xff) as u8)
+                .collect()
+        }
+        BitDepth::Sixteen => {
+            encoder.set(png::BitDepth::Sixteen);
+            values
+                .into_iter()
+                .flat_map(|v| {
+                    let v = quantize(v, 0xffff);
+                    vec![(v >> 8) as u8, v as u8]
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
+
+fn quantize(value: f64, max: u32) -> u32 {
+    (value.clamp(0.0, 1.0) * f64::from(max)).round() as u32
+}
+
+fn read_pgm<R: BufRead>(mut reader: R) -> io::Result<(usize, usize, Vec<f64>)> {
+    let magic = read_pgm_token(&mut reader)?;
+    let binary = match magic.as_str() {
+        "P5" => true,
+        "P2" => false,
+        _ => {
+            return Err(invalid_data(format!(
+                "invalid pgm magic number {:?}",
+                magic
+            )))
+        }
+    };
+
+    let mut header = [0; 3];
+    for value in &mut header {
+        let token = read_pgm_token(&mut reader)?;
+        *value = token
+            .parse::<usize>()
+            .map_err(|_| invalid_data(format!("invalid pgm header value {:?}", token)))?;
+    }
+    let [width, height, max_value] = header;
+
+    if max_value == 0 || max_value > 0xffff {
+        return Err(invalid_data(format!("invalid pgm max value {}", max_value)));
+    }
+
+    let len = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid_data(format!("pgm image too large, {}x{}", width, height)))?;
+    let samples = if binary {
+        let bytes_per_sample = if max_value > 0xff { 2 } else { 1 };
+        let bytes = len
+            .checked_mul(bytes_per_sample)
+            .ok_or_else(|| invalid_data(format!("pgm image too large, {}x{}", width, height)))?;
+
+        // Only allocate as much as the input holds, the header could claim any size.
+        let mut buf = Vec::new();
+        reader.take(bytes as u64).read_to_end(&mut buf)?;
+        if buf.len() != bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected end of pgm raster",
+            ));
+        }
+
+        if bytes_per_sample == 2 {
+            buf.chunks(2)
+                .map(|c| usize::from(c[0]) << 8 | usize::from(c[1]))
+                .collect::<Vec<_>>()
+        } else {
+            buf.into_iter().map(usize::from).collect()
+        }
+    } else {
+        let mut samples = Vec::new();
+        for _ in 0..len {
+            let token = read_pgm_token(&mut reader)?;
+            samples.push(
+                token
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data(format!("invalid pgm sample {:?}", token)))?,
+            );
+        }
+        samples
+    };
+
+    let data = samples
+        .into_iter()
+        .map(|s| (s as f64 / max_value as f64).min(1.0))
+        .collect();
+
+    Ok((width, height, data))
+}
+
+/// Reads a whitespace separated token, skipping comments. Consumes the single whitespace
+/// character following the token, as required before the binary raster.
+fn read_pgm_token<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut token = String::new();
+    let mut comment = false;
+
+    loop {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if token.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unexpected end of pgm header",
+                ));
+            }
+            return Ok(token);
+        }
+
+        let c = byte[0] as char;
+        if comment {
+            comment = c != '\n' && c != '\r';
+        } else if c == '#' {
+            comment = true;
+        } else if c.is_ascii_whitespace() {
+            if !token.is_empty() {
+                return Ok(token);
+            }
+        } else {
+            token.push(c);
+        }
+    }
+}
+
+fn write_pgm<W: Write>(mut writer: W, map: &ArraySlopeMap, depth: BitDepth) -> io::Result<()> {
+    let max_value = match depth {
+        BitDepth::Eight => 0xff,
+        BitDepth::Sixteen => 0xffff,
+    };
+
+    write!(writer, "P5\n{} {}\n{}\n", map.size, map.size, max_value)?;
+    for &v in &map.data {
+        let v = quantize(v, max_value);
+        match depth {
+            BitDepth::Eight => writer.write_all(&[v as u8])?,
+            BitDepth::Sixteen => writer.write_all(&[(v >> 8) as u8, v as u8])?,
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use slope_map::SlopeMap;
+    use {Point2, Vector2};
+
+    fn array_slope_map() -> ArraySlopeMap {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.2, 0.2, 0.0,
+            0.2, 0.4, 0.2, 0.2,
+            0.0, 0.4, 1.0, 0.0,
+            0.2, 0.2, 0.0, 0.0,
+        ];
+
+        ArraySlopeMap::new(data, 4, Vector2::new(45.0, -10.0), 10.0)
+    }
+
+    fn assert_close(a: &ArraySlopeMap, b: &ArraySlopeMap, epsilon: f64) {
+        assert_eq!(a.size, b.size);
+        for (x, y) in a.data.iter().zip(b.data.iter()) {
+            assert!((x - y).abs() <= epsilon, "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn pgm_round_trip() {
+        let map = array_slope_map();
+        let offset = Vector2::new(45.0, -10.0);
+
+        for &(depth, epsilon) in &[(BitDepth::Eight, 0.5 / 255.0), (BitDepth::Sixteen, 1e-5)] {
+            let mut buf = Vec::new();
+            map.write_image(&mut buf, ImageFormat::Pgm, depth).unwrap();
+
+            let read = ArraySlopeMap::read_image(&buf[..], ImageFormat::Pgm, offset, 10.0).unwrap();
+            assert_close(&map, &read, epsilon);
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let map = array_slope_map();
+        let offset = Vector2::new(45.0, -10.0);
+
+        for &(depth, epsilon) in &[(BitDepth::Eight, 0.5 / 255.0), (BitDepth::Sixteen, 1e-5)] {
+            let mut buf = Vec::new();
+            map.write_image(&mut buf, ImageFormat::Png, depth).unwrap();
+
+            let read = ArraySlopeMap::read_image(&buf[..], ImageFormat::Png, offset, 10.0).unwrap();
+            assert_close(&map, &read, epsilon);
+        }
+    }
+
+    #[test]
+    fn plain_pgm() {
+        let image = b"P2\n# painted slope map\n2 2\n4\n0 1\n2 4\n";
+        let map =
+            ArraySlopeMap::read_image(&image[..], ImageFormat::Pgm, Vector2::new(0.0, 0.0), 2.0)
+                .unwrap();
+
+        assert_eq!(map.sample(Point2::new(0.5, 0.5)), 0.0);
+        assert_eq!(map.sample(Point2::new(1.5, 0.5)), 0.25);
+        assert_eq!(map.sample(Point2::new(0.5, 1.5)), 0.5);
+        assert_eq!(map.sample(Point2::new(1.5, 1.5)), 1.0);
+    }
+
+    #[test]
+    fn non_square_image() {
+        let image = b"P2 2 1 4 0 1";
+        let map =
+            ArraySlopeMap::read_image(&image[..], ImageFormat::Pgm, Vector2::new(0.0, 0.0), 2.0);
+
+        assert_eq!(
+            map.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn truncated_pgm() {
+        for image in &[
+            &b"P5 100000 100000 65535\n\0\0"[..],
+            &b"P5 4294967296 4294967296 255\n"[..],
+        ] {
+            let map = ArraySlopeMap::read_image(
+                &image[..],
+                ImageFormat::Pgm,
+                Vector2::new(0.0, 0.0),
+                2.0,
+            );
+            assert!(map.is_err());
+        }
+    }
+
+    #[test]
+    fn empty_image() {
+        let map = ArraySlopeMap::read_image(
+            &b"P5 0 0 255\n"[..],
+            ImageFormat::Pgm,
+            Vector2::new(0.0, 0.0),
+            2.0,
+        );
+        match map {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("loaded an empty image"),
+        }
+    }
+
+    #[test]
+    fn north_up_world_file() {
+        let dir = env::temp_dir().join(format!("terrain-north-up-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("slope.pgm");
+
+        // the top row is the northern edge of the square from (100, 200) to (104, 204)
+        fs::write(&path, b"P2\n2 2\n4\n0 1\n2 4\n").unwrap();
+        fs::write(dir.join("slope.pgmw"), b"2\n0\n0\n-2\n101\n203\n").unwrap();
+
+        let map = ArraySlopeMap::load(&path).unwrap();
+        assert_eq!(map.sample(Point2::new(101.0, 203.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(103.0, 203.0)), 0.25);
+        assert_eq!(map.sample(Point2::new(101.0, 201.0)), 0.5);
+        assert_eq!(map.sample(Point2::new(103.0, 201.0)), 1.0);
+        assert_eq!(map.sample(Point2::new(101.0, 205.0)), 0.0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = env::temp_dir().join(format!("terrain-slope-map-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("slope.png");
+
+        let map = array_slope_map();
+        map.save(&path, BitDepth::Sixteen).unwrap();
+        assert!(dir.join("slope.pngw").exists());
+
+        let loaded = ArraySlopeMap::load(&path).unwrap();
+        assert_close(&map, &loaded, 1e-5);
+        assert!((loaded.offset - map.offset).norm() < 1e-9);
+        assert!((loaded.scale - map.scale).abs() < 1e-9);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+use std::f64;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use {Point2, Vector2};
+
+pub mod adapters;
+mod generators;
+mod heightmap;
+mod image;
+mod quadtree;
+
+pub use self::generators::{Brush, Falloff, Stroke};
+pub use self::heightmap::{HeightmapSettings, HeightmapSlopeMap, SlopeMapping};
+pub(crate) use self::image::encode_png;
+pub use self::image::{BitDepth, ImageFormat};
+pub use self::quadtree::{QuadtreeSettings, QuadtreeSlopeMap};
+
+use self::adapters::{Add, Clamp, Lerp, Max, Min, Multiply, Offset, Remap, Scale, Transform};
+
+pub struct ArraySlopeMap {
+    data: Vec<f64>,
+    size: usize,
+
+    offset: Vector2,
+    scale: f64,
+}
+
+impl ArraySlopeMap {
+    pub fn new(data: Vec<f64>, size: usize, offset: Vector2, scale: f64) -> ArraySlopeMap {
+        assert!(size > 0, "slope maps need at least one sample");
+        assert_eq!(size * size, data.len());
+        ArraySlopeMap {
+            data,
+            size,
+            offset,
+            scale: scale.recip(),
+        }
+    }
+}
+
+impl SlopeMap for ArraySlopeMap {
+    fn sample(&self, pos: Point2) -> f64 {
+        let pos = (pos - self.offset) * self.scale;
+
+        if pos.x < 0.0 || pos.x >= 1.0 || pos.y < 0.0 || pos.y >= 1.0 {
+            return 0.0;
+        }
+
+        let x = (pos.x * self.size as f64) as usize;
+        let y = (pos.y * self.size as f64) as usize;
+
+        let idx = x + y * self.size;
+
+        let val = self.data[idx];
+        assert!((0.0..=1.0).contains(&val), "val {}", val);
+
+        val
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        let pos = (pos - self.offset) * self.scale;
+        pos.x >= 0.0 && pos.x < 1.0 && pos.y >= 0.0 && pos.y < 1.0
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let pos = (pos - self.offset) * self.scale;
+
+        if pos.x < 0.0 || pos.x >= 1.0 || pos.y < 0.0 || pos.y >= 1.0 {
+            return Vector2::zeros();
+        }
+
+        let x = (pos.x * self.size as f64) as usize;
+        let y = (pos.y * self.size as f64) as usize;
+        let get = |x: usize, y: usize| self.data[x + y * self.size];
+
+        // Central differences between the neighbouring cells, one sided along the edges.
+        let cell_size = (self.scale * self.size as f64).recip();
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.size - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(self.size - 1));
+
+        let dx = if x1 > x0 {
+            (get(x1, y) - get(x0, y)) / ((x1 - x0) as f64 * cell_size)
+        } else {
+            0.0
+        };
+        let dy = if y1 > y0 {
+            (get(x, y1) - get(x, y0)) / ((y1 - y0) as f64 * cell_size)
+        } else {
+            0.0
+        };
+
+        Vector2::new(dx, dy)
+    }
+}
+
+/// Step used by the default finite difference implementation of `SlopeMap::gradient`.
+pub const GRADIENT_STEP: f64 = 1.0;
+
+/// Summary of the slope map values along a line segment, see `SlopeMap::sample_segment`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentSample {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// A slope map backed by a closure, created by [`from_fn`](fn.from_fn.html).
+#[derive(Clone)]
+pub struct FromFn<F>(F);
+
+/// Creates a slope map from a closure, the closure must return values in the range [0.0, 1.0].
+pub fn from_fn<F: Fn(Point2) -> f64>(f: F) -> FromFn<F> {
+    FromFn(f)
+}
+
+impl<F: Fn(Point2) -> f64> SlopeMap for FromFn<F> {
+    fn sample(&self, pos: Point2) -> f64 {
+        let val = (self.0)(pos);
+        assert!((0.0..=1.0).contains(&val), "val {}", val);
+
+        val
+    }
+}
+
+impl<SM: SlopeMap + ?Sized> SlopeMap for &SM {
+    fn sample(&self, pos: Point2) -> f64 {
+        (**self).sample(pos)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        (**self).covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        (**self).gradient(pos)
+    }
+}
+
+impl<SM: SlopeMap + ?Sized> SlopeMap for Box<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        (**self).sample(pos)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        (**self).covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        (**self).gradient(pos)
+    }
+}
+
+impl<SM: SlopeMap + ?Sized> SlopeMap for Rc<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        (**self).sample(pos)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        (**self).covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        (**self).gradient(pos)
+    }
+}
+
+impl<SM: SlopeMap + ?Sized> SlopeMap for Arc<SM> {
+    fn sample(&self, pos: Point2) -> f64 {
+        (**self).sample(pos)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        (**self).covers(pos)
+    }
+
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        (**self).gradient(pos)
+    }
+}
+
+pub trait SlopeMap {
+    /// Valid values [0.0, 1.0]
+    fn sample(&self, pos: Point2) -> f64;
+
+    /// Whether the slope map is defined at `pos`, maps covering a limited area sample 0.0
+    /// outside of it.
+    fn covers(&self, _pos: Point2) -> bool {
+        true
+    }
+
+    /// The direction and rate of the steepest increase of the slope map at `pos`.
+    ///
+    /// The default implementation uses central differences with a step of `GRADIENT_STEP`.
+    fn gradient(&self, pos: Point2) -> Vector2 {
+        let dx = Vector2::new(GRADIENT_STEP, 0.0);
+        let dy = Vector2::new(0.0, GRADIENT_STEP);
+
+        Vector2::new(
+            self.sample(pos + dx) - self.sample(pos - dx),
+            self.sample(pos + dy) - self.sample(pos - dy),
+        ) / (2.0 * GRADIENT_STEP)
+    }
+
+    /// Rate of change of the slope map at `pos` in the direction of `direction`.
+    fn directional_derivative(&self, pos: Point2, direction: Vector2) -> f64 {
+        let norm = direction.norm();
+        if norm > 0.0 {
+            self.gradient(pos).dot(&direction) / norm
+        } else {
+            0.0
+        }
+    }
+
+    /// Samples `samples` evenly spaced points from `a` to `b`, both included.
+    fn sample_segment(&self, a: Point2, b: Point2, samples: usize) -> SegmentSample {
+        assert!(samples >= 2, "samples {}", samples);
+
+        let mut min = f64::INFINITY;
+        let mut max = f64::NEG_INFINITY;
+        let mut sum = 0.0;
+        for i in 0..samples {
+            let t = i as f64 / (samples - 1) as f64;
+            let val = self.sample(a + (b - a) * t);
+            min = min.min(val);
+            max = max.max(val);
+            sum += val;
+        }
+
+        SegmentSample {
+            min,
+            max,
+            mean: sum / samples as f64,
+        }
+    }
+
+    /// Multiplies every sample by `factor`.
+    fn scale(self, factor: f64) -> Scale<Self>
+    where
+        Self: Sized,
+    {
+        Scale::new(self, factor)
+    }
+
+    /// Adds `offset` to every sample.
+    fn offset(self, offset: f64) -> Offset<Self>
+    where
+        Self: Sized,
+    {
+        Offset::new(self, offset)
+    }
+
+    /// Adds the samples of `other` to the samples of this slope map.
+    fn plus<SM: SlopeMap>(self, other: SM) -> Add<Self, SM>
+    where
+        Self: Sized,
+    {
+        Add::new(self, other)
+    }
+
+    /// Multiplies the samples of this slope map with the samples of `other`.
+    fn multiply<SM: SlopeMap>(self, other: SM) -> Multiply<Self, SM>
+    where
+        Self: Sized,
+    {
+        Multiply::new(self, other)
+    }
+
+    /// Takes the smallest sample of this slope map and `other`.
+    fn min<SM: SlopeMap>(self, other: SM) -> Min<Self, SM>
+    where
+        Self: Sized,
+    {
+        Min::new(self, other)
+    }
+
+    /// Takes the largest sample of this slope map and `other`.
+    fn max<SM: SlopeMap>(self, other: SM) -> Max<Self, SM>
+    where
+        Self: Sized,
+    {
+        Max::new(self, other)
+    }
+
+    /// Interpolates between this slope map and `other`, `mask` selects `other` where it is 1.0.
+    fn lerp<SM: SlopeMap, M: SlopeMap>(self, other: SM, mask: M) -> Lerp<Self, SM, M>
+    where
+        Self: Sized,
+    {
+        Lerp::new(self, other, mask)
+    }
+
+    /// Limits samples to the range [min, max].
+    fn clamp(self, min: f64, max: f64) -> Clamp<Self>
+    where
+        Self: Sized,
+    {
+        Clamp::new(self, min, max)
+    }
+
+    /// Passes every sample through `curve`.
+    fn remap<F: Fn(f64) -> f64>(self, curve: F) -> Remap<Self, F>
+    where
+        Self: Sized,
+    {
+        Remap::new(self, curve)
+    }
+
+    /// Transforms the position with `transform` before sampling.
+    fn transform<F: Fn(Point2) -> Point2>(self, transform: F) -> Transform<Self, F>
+    where
+        Self: Sized,
+    {
+        Transform::new(self, transform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use {Point2, Vector2};
+
+    fn array_slope_map() -> ArraySlopeMap {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.1, 0.1, 0.0,
+            0.1, 0.2, 0.1, 0.1,
+            0.0, 0.2, 0.0, 0.0,
+            0.1, 0.1, 0.0, 0.0,
+        ];
+
+        ArraySlopeMap::new(data, 4, Vector2::new(45.0, -10.0), 10.0)
+    }
+
+    #[test]
+    fn create_array_slope_map() {
+        let _map = array_slope_map();
+    }
+
+    #[test]
+    fn array_slope_map_sample() {
+        let map = array_slope_map();
+
+        // points outside the map
+        assert_eq!(map.sample(Point2::new(0.0, 0.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(60.0, 0.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(0.0, -20.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(0.0, 10.0)), 0.0);
+
+        // points on the map
+        assert_eq!(map.sample(Point2::new(45.0, -10.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(47.5, -10.0)), 0.1);
+        assert_eq!(map.sample(Point2::new(47.5, -7.5)), 0.2);
+    }
+
+    #[test]
+    fn array_slope_map_gradient() {
+        let map = array_slope_map();
+
+        // points outside the map
+        assert_eq!(map.gradient(Point2::new(0.0, 0.0)), Vector2::zeros());
+
+        // points on the map, cells are 2.5 wide
+        let gradient = |x, y| map.gradient(Point2::new(x, y));
+        assert!((gradient(45.0, -10.0) - Vector2::new(0.04, 0.04)).norm() < 1e-9);
+        assert!((gradient(47.5, -7.5) - Vector2::new(0.0, 0.02)).norm() < 1e-9);
+        assert!((gradient(52.5, -5.0) - Vector2::new(0.0, -0.02)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn finite_difference_gradient() {
+        let map = from_fn(|pos| (pos.x * 0.01 + pos.y * 0.02).clamp(0.0, 1.0));
+        let gradient = map.gradient(Point2::new(10.0, 10.0));
+
+        assert!((gradient - Vector2::new(0.01, 0.02)).norm() < 1e-9);
+        assert!(
+            (map.directional_derivative(Point2::new(10.0, 10.0), Vector2::new(3.0, 0.0)) - 0.01)
+                .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn segment_sample() {
+        let map = array_slope_map();
+        let sample = map.sample_segment(Point2::new(45.0, -9.0), Point2::new(54.0, -9.0), 4);
+
+        assert_eq!(sample.min, 0.0);
+        assert_eq!(sample.max, 0.1);
+        assert!((sample.mean - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shared_slope_maps() {
+        use std::sync::Arc;
+
+        fn sample<SM: SlopeMap>(map: SM) -> f64 {
+            map.sample(Point2::new(47.5, -7.5))
+        }
+
+        let map = array_slope_map();
+        assert_eq!(sample(&map), 0.2);
+        assert_eq!(sample::<&&ArraySlopeMap>(&&map), 0.2);
+
+        let boxed: Box<dyn SlopeMap> = Box::new(array_slope_map());
+        assert_eq!(sample(&boxed), 0.2);
+        assert_eq!(sample(boxed), 0.2);
+
+        let shared = Arc::new(array_slope_map());
+        assert_eq!(sample(shared.clone()), 0.2);
+        assert_eq!(sample(shared), 0.2);
+    }
+
+    #[test]
+    fn closure_slope_map() {
+        let map = from_fn(|pos| if pos.x < 0.0 { 0.0 } else { 0.5 });
+
+        assert_eq!(map.sample(Point2::new(-1.0, 0.0)), 0.0);
+        assert_eq!(map.sample(Point2::new(1.0, 0.0)), 0.5);
+        assert_eq!(map.scale(0.5).sample(Point2::new(1.0, 0.0)), 0.25);
+    }
+}
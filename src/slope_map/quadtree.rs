@@ -0,0 +1,369 @@
+//! A sparse, multi-resolution slope map for very large worlds.
+//!
+//! The slope map is the sum of bilinear "hat" functions on a hierarchy of grids, where the grid at
+//! depth `d` has `2^d` cells along each side. Every vertex only stores the difference (the
+//! surplus) between the detailed value and the interpolation of the coarser grids, so the surplus
+//! is zero wherever there is no extra detail and those vertices are left out of the tree. Since
+//! each hat function is continuous, the slope map is continuous across cells of different depths.
+
+use slope_map::SlopeMap;
+use {Point2, Vector2};
+
+#[derive(Clone, Debug)]
+pub struct QuadtreeSettings {
+    /// Cells are always visited down to this depth, so detail larger than `size / 2^min_depth`
+    /// is never missed. Cells without detail still take no memory.
+    ///
+    /// **Example value:** 6
+    pub min_depth: usize,
+
+    /// The deepest level of the tree, cells at this level are `size / 2^max_depth` wide.
+    ///
+    /// **Example value:** 10
+    pub max_depth: usize,
+
+    /// Cells are only refined where the source differs from the coarser levels by more than
+    /// this.
+    ///
+    /// **Example value:** 0.01
+    pub tolerance: f64,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    /// Surpluses of the corners of this cell at this depth, in the order
+    /// (0, 0), (1, 0), (0, 1), (1, 1).
+    corners: [f64; 4],
+    children: Option<Box<[Node; 4]>>,
+}
+
+impl Node {
+    fn new() -> Node {
+        Node {
+            corners: [0.0; 4],
+            children: None,
+        }
+    }
+
+    fn count(&self) -> usize {
+        1 + self
+            .children
+            .as_ref()
+            .map(|c| c.iter().map(Node::count).sum())
+            .unwrap_or(0)
+    }
+}
+
+/// Bilinear interpolation of the corners of a cell.
+fn bilinear(corners: &[f64; 4], u: f64, v: f64) -> f64 {
+    let top = corners[0] * (1.0 - u) + corners[1] * u;
+    let bottom = corners[2] * (1.0 - u) + corners[3] * u;
+    top * (1.0 - v) + bottom * v
+}
+
+/// A slope map backed by a quadtree covering the square from `offset` to `offset + size`.
+#[derive(Clone, Debug)]
+pub struct QuadtreeSlopeMap {
+    root: Node,
+
+    offset: Vector2,
+    size: f64,
+}
+
+impl QuadtreeSlopeMap {
+    /// Creates a slope map with the same value everywhere.
+    pub fn new(offset: Vector2, size: f64, value: f64) -> QuadtreeSlopeMap {
+        assert!(size > 0.0);
+        assert!((0.0..=1.0).contains(&value), "value {}", value);
+
+        QuadtreeSlopeMap {
+            root: Node {
+                corners: [value; 4],
+                children: None,
+            },
+            offset,
+            size,
+        }
+    }
+
+    /// Approximates `source`, refining the tree only where it has detail.
+    pub fn from_slope_map<SM: SlopeMap>(
+        source: &SM,
+        offset: Vector2,
+        size: f64,
+        settings: &QuadtreeSettings,
+    ) -> QuadtreeSlopeMap {
+        let mut map = QuadtreeSlopeMap::new(offset, size, 0.0);
+        for (i, &(x, y)) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().enumerate() {
+            map.root.corners[i] = source.sample(map.vertex_pos(0, x, y));
+        }
+
+        let (min, max) = (
+            Point2::from(offset),
+            Point2::from(offset) + Vector2::repeat(size),
+        );
+        map.refine(source, min, max, settings);
+
+        map
+    }
+
+    /// Adds the detail of `source` within the rectangle from `min` to `max`, such as a region
+    /// painted by an artist. Only vertices within the rectangle are changed, so the rest of the
+    /// slope map is left as it is apart from a transition band along the edges.
+    pub fn refine<SM: SlopeMap>(
+        &mut self,
+        source: &SM,
+        min: Point2,
+        max: Point2,
+        settings: &QuadtreeSettings,
+    ) {
+        let min = (min - self.offset) / self.size;
+        let max = (max - self.offset) / self.size;
+        self.refine_cell(source, (min, max), settings, 0, 0, 0);
+    }
+
+    /// Number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        self.root.count()
+    }
+
+    fn refine_cell<SM: SlopeMap>(
+        &mut self,
+        source: &SM,
+        region: (Point2, Point2),
+        settings: &QuadtreeSettings,
+        depth: usize,
+        x: u64,
+        y: u64,
+    ) {
+        if depth >= settings.max_depth {
+            return;
+        }
+
+        // Skip cells outside of the region.
+        let cell_size = 0.5f64.powi(depth as i32);
+        let (min, max) = region;
+        if (x + 1) as f64 * cell_size <= min.x
+            || x as f64 * cell_size >= max.x
+            || (y + 1) as f64 * cell_size <= min.y
+            || y as f64 * cell_size >= max.y
+        {
+            return;
+        }
+
+        // The vertices introduced by splitting this cell, in the grid of the next depth.
+        let (cx, cy) = (x * 2 + 1, y * 2 + 1);
+        let vertices = [
+            (cx, cy - 1),
+            (cx - 1, cy),
+            (cx, cy),
+            (cx + 1, cy),
+            (cx, cy + 1),
+        ];
+
+        let mut refine = false;
+        for &(vx, vy) in &vertices {
+            let local = self.vertex_local(depth + 1, vx, vy);
+            if local.x < min.x || local.x > max.x || local.y < min.y || local.y > max.y {
+                continue;
+            }
+
+            let residual =
+                source.sample(self.vertex_pos(depth + 1, vx, vy)) - self.sample_local(local);
+            if residual.abs() > settings.tolerance {
+                self.add_surplus(depth + 1, vx, vy, residual);
+                refine = true;
+            }
+        }
+
+        refine |= depth + 1 < settings.min_depth;
+
+        // Look for detail that falls between the vertices of the next depth.
+        if !refine {
+            for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (vx, vy) = ((x * 2 + dx) * 2 + 1, (y * 2 + dy) * 2 + 1);
+                let residual = source.sample(self.vertex_pos(depth + 2, vx, vy))
+                    - self.sample_local(self.vertex_local(depth + 2, vx, vy));
+                refine |= residual.abs() > settings.tolerance;
+            }
+        }
+
+        if refine {
+            for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                self.refine_cell(source, region, settings, depth + 1, x * 2 + dx, y * 2 + dy);
+            }
+        }
+    }
+
+    /// Adds to the surplus of the vertex `(x, y)` in the grid at `depth`, creating the nodes of
+    /// every cell around it so that absent nodes always mean zero surpluses.
+    fn add_surplus(&mut self, depth: usize, x: u64, y: u64, surplus: f64) {
+        let cells = 1u64 << depth;
+        for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            if x < dx || y < dy || x - dx >= cells || y - dy >= cells {
+                continue;
+            }
+
+            let node = self.node_mut(depth, x - dx, y - dy);
+            node.corners[(dx + dy * 2) as usize] += surplus;
+        }
+    }
+
+    fn node_mut(&mut self, depth: usize, x: u64, y: u64) -> &mut Node {
+        let mut node = &mut self.root;
+        for level in (0..depth).rev() {
+            let child = ((x >> level) & 1) + ((y >> level) & 1) * 2;
+            node = &mut node.children.get_or_insert_with(|| {
+                Box::new([Node::new(), Node::new(), Node::new(), Node::new()])
+            })[child as usize];
+        }
+        node
+    }
+
+    fn vertex_local(&self, depth: usize, x: u64, y: u64) -> Point2 {
+        let cell_size = 0.5f64.powi(depth as i32);
+        Point2::new(x as f64 * cell_size, y as f64 * cell_size)
+    }
+
+    fn vertex_pos(&self, depth: usize, x: u64, y: u64) -> Point2 {
+        self.vertex_local(depth, x, y) * self.size + self.offset
+    }
+
+    /// Sums the contributions of every depth at a position in [0.0, 1.0].
+    fn sample_local(&self, pos: Point2) -> f64 {
+        let (mut u, mut v) = (pos.x, pos.y);
+        let mut node = &self.root;
+        let mut val = 0.0;
+
+        loop {
+            val += bilinear(&node.corners, u, v);
+
+            match node.children {
+                Some(ref children) => {
+                    let (right, bottom) = (u >= 0.5, v >= 0.5);
+                    u = u * 2.0 - if right { 1.0 } else { 0.0 };
+                    v = v * 2.0 - if bottom { 1.0 } else { 0.0 };
+                    node = &children[right as usize + bottom as usize * 2];
+                }
+                None => return val,
+            }
+        }
+    }
+}
+
+impl SlopeMap for QuadtreeSlopeMap {
+    fn sample(&self, pos: Point2) -> f64 {
+        let pos = (pos - self.offset) / self.size;
+
+        if pos.x < 0.0 || pos.x > 1.0 || pos.y < 0.0 || pos.y > 1.0 {
+            return 0.0;
+        }
+
+        let pos = Point2::new(pos.x, pos.y);
+        self.sample_local(pos).clamp(0.0, 1.0)
+    }
+
+    fn covers(&self, pos: Point2) -> bool {
+        let pos = (pos - self.offset) / self.size;
+        pos.x >= 0.0 && pos.x <= 1.0 && pos.y >= 0.0 && pos.y <= 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use slope_map::from_fn;
+    use {Point2, Vector2};
+
+    fn settings() -> QuadtreeSettings {
+        QuadtreeSettings {
+            min_depth: 6,
+            max_depth: 8,
+            tolerance: 0.001,
+        }
+    }
+
+    /// A small painted dab in an otherwise gently sloping world, centered on a vertex at depth 8.
+    fn dab(pos: Point2) -> f64 {
+        let d = (pos - Point2::new(312.5, 687.5)).norm();
+        let dab = (1.0 - d / 20.0).max(0.0) * 0.5;
+        0.1 + pos.x * 0.0001 + dab
+    }
+
+    #[test]
+    fn constant_quadtree() {
+        let map = QuadtreeSlopeMap::new(Vector2::new(-10.0, -10.0), 20.0, 0.3);
+
+        assert_eq!(map.sample(Point2::new(0.0, 0.0)), 0.3);
+        assert_eq!(map.sample(Point2::new(10.0, 10.0)), 0.3);
+        assert_eq!(map.sample(Point2::new(11.0, 0.0)), 0.0);
+        assert_eq!(map.node_count(), 1);
+    }
+
+    #[test]
+    fn linear_source_is_not_refined() {
+        let source = from_fn(|p: Point2| 0.1 + p.x * 0.0002 + p.y * 0.0003);
+        let map = QuadtreeSlopeMap::from_slope_map(&source, Vector2::zeros(), 1000.0, &settings());
+
+        assert_eq!(map.node_count(), 1);
+        let pos = Point2::new(123.0, 456.0);
+        assert!((map.sample(pos) - source.sample(pos)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn refined_around_detail() {
+        let source = from_fn(dab);
+        let map = QuadtreeSlopeMap::from_slope_map(&source, Vector2::zeros(), 1000.0, &settings());
+
+        // A full tree of depth 8 has more than 80 000 nodes.
+        assert!(map.node_count() < 1000, "node_count {}", map.node_count());
+
+        for &pos in &[
+            Point2::new(312.5, 687.5),
+            Point2::new(320.0, 690.0),
+            Point2::new(100.0, 100.0),
+            Point2::new(900.0, 200.0),
+        ] {
+            assert!(
+                (map.sample(pos) - dab(pos)).abs() < 0.02,
+                "pos {} sample {} source {}",
+                pos,
+                map.sample(pos),
+                dab(pos)
+            );
+        }
+    }
+
+    #[test]
+    fn continuous_between_depths() {
+        let source = from_fn(dab);
+        let map = QuadtreeSlopeMap::from_slope_map(&source, Vector2::zeros(), 1000.0, &settings());
+
+        // Walk across the boundary between refined and coarse cells.
+        let mut previous = map.sample(Point2::new(200.0, 700.0));
+        for i in 1..2000 {
+            let val = map.sample(Point2::new(200.0 + i as f64 * 0.1, 700.0));
+            assert!((val - previous).abs() < 0.01, "jump at {}", i);
+            previous = val;
+        }
+    }
+
+    #[test]
+    fn refine_painted_region() {
+        let mut map = QuadtreeSlopeMap::new(Vector2::zeros(), 1000.0, 0.1);
+        let painted = from_fn(|p: Point2| if p.x > 500.0 { 0.6 } else { 0.1 });
+
+        map.refine(
+            &painted,
+            Point2::new(0.0, 0.0),
+            Point2::new(1000.0, 250.0),
+            &settings(),
+        );
+
+        // Inside the region the painted edge is picked up, the rest is untouched.
+        assert!((map.sample(Point2::new(700.0, 100.0)) - 0.6).abs() < 0.01);
+        assert!((map.sample(Point2::new(300.0, 100.0)) - 0.1).abs() < 0.01);
+        assert!((map.sample(Point2::new(700.0, 900.0)) - 0.1).abs() < 0.01);
+    }
+}
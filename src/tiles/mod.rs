@@ -0,0 +1,367 @@
+//! Quadtree of tiles for streaming the terrain at different levels of detail.
+
+use heightfield::{sample_grid, Heightfield};
+use mesh::{grid_indices, Mesh};
+use primitives::{BoundingBox, TerrainPrimitive};
+use {Point2, Point3, Vector3};
+
+#[derive(Clone, Debug)]
+pub struct TileSettings {
+    /// Number of samples along each side of a tile, one more than a power of two so that the
+    /// samples of a tile are also samples of its children.
+    ///
+    /// **Example value:** 65
+    pub resolution: usize,
+
+    /// Finest level of detail, where the root tile has been split this many times.
+    ///
+    /// **Example value:** 6
+    pub max_lod: u32,
+
+    /// Tiles closer to the viewer than this many times their size are split.
+    ///
+    /// **Example value:** 1.5
+    pub lod_distance: f64,
+
+    /// How far below the edges of a mesh tile its skirts reach, hiding the cracks to neighbours
+    /// at another level of detail.
+    ///
+    /// **Example value:** 50.0
+    pub skirt_depth: f64,
+
+    /// Elevation of the samples outside the contour.
+    ///
+    /// **Example value:** 0.0
+    pub sea_level: f64,
+
+    /// Elevation of the samples inside the contour where no primitive has a weight.
+    ///
+    /// **Example value:** 0.0
+    pub base_elevation: f64,
+}
+
+/// A tile of the quadtree. Level `lod` has `2^lod` by `2^lod` tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub x: u32,
+    pub y: u32,
+    pub lod: u32,
+}
+
+impl TileId {
+    pub fn root() -> TileId {
+        TileId { x: 0, y: 0, lod: 0 }
+    }
+
+    pub fn parent(&self) -> Option<TileId> {
+        if self.lod == 0 {
+            return None;
+        }
+
+        Some(TileId {
+            x: self.x / 2,
+            y: self.y / 2,
+            lod: self.lod - 1,
+        })
+    }
+
+    pub fn children(&self) -> [TileId; 4] {
+        let child = |dx, dy| TileId {
+            x: self.x * 2 + dx,
+            y: self.y * 2 + dy,
+            lod: self.lod + 1,
+        };
+
+        [child(0, 0), child(1, 0), child(0, 1), child(1, 1)]
+    }
+}
+
+/// Splits the square around the contour into tiles.
+///
+/// Tiles only depend on the terrain and their id, so a terrain built with a fixed seed gives the
+/// same tiles every time. Neighbouring tiles share the samples along their common edge, and the
+/// samples of a tile are also samples of its children, computed from the same global index.
+#[derive(Clone, Debug)]
+pub struct Tiling {
+    contour: Vec<Point2>,
+    origin: Point2,
+    size: f64,
+    settings: TileSettings,
+}
+
+impl Tiling {
+    pub fn new(contour: Vec<Point2>, settings: TileSettings) -> Tiling {
+        assert!(!contour.is_empty());
+        assert!(settings.resolution >= 2 && (settings.resolution - 1).is_power_of_two());
+        assert!(settings.lod_distance >= 0.0);
+
+        let bounding_box = contour
+            .iter()
+            .skip(1)
+            .fold(BoundingBox::new(contour[0], contour[0]), |bb, &p| {
+                bb.union(&BoundingBox::new(p, p))
+            });
+        let extent = bounding_box.max - bounding_box.min;
+        let size = extent.x.max(extent.y);
+        assert!(size > 0.0);
+
+        Tiling {
+            contour,
+            origin: bounding_box.min,
+            size,
+            settings,
+        }
+    }
+
+    /// Distance between two samples of a tile at level `lod`.
+    pub fn cell_size(&self, lod: u32) -> f64 {
+        self.size / ((self.settings.resolution - 1) << lod) as f64
+    }
+
+    pub fn bounding_box(&self, tile: TileId) -> BoundingBox {
+        let last = self.settings.resolution as i64 - 1;
+        BoundingBox::new(self.position(tile, 0, 0), self.position(tile, last, last))
+    }
+
+    /// Position of sample `(x, y)` of the tile, which may lie outside the tile.
+    fn position(&self, tile: TileId, x: i64, y: i64) -> Point2 {
+        let n = self.settings.resolution as i64 - 1;
+        let cell_size = self.cell_size(tile.lod);
+
+        Point2::new(
+            self.origin.x + (i64::from(tile.x) * n + x) as f64 * cell_size,
+            self.origin.y + (i64::from(tile.y) * n + y) as f64 * cell_size,
+        )
+    }
+
+    /// The tiles to draw for a viewer at `viewer`, finer closer to the viewer. Together they
+    /// cover the whole tiling without overlapping.
+    pub fn select(&self, viewer: Point2) -> Vec<TileId> {
+        let mut tiles = Vec::new();
+        let mut stack = vec![TileId::root()];
+
+        while let Some(tile) = stack.pop() {
+            let bb = self.bounding_box(tile);
+            let dx = (bb.min.x - viewer.x).max(viewer.x - bb.max.x).max(0.0);
+            let dy = (bb.min.y - viewer.y).max(viewer.y - bb.max.y).max(0.0);
+            let distance = (dx * dx + dy * dy).sqrt();
+
+            let size = bb.max.x - bb.min.x;
+            if tile.lod < self.settings.max_lod && distance < size * self.settings.lod_distance {
+                stack.extend_from_slice(&tile.children());
+            } else {
+                tiles.push(tile);
+            }
+        }
+
+        tiles
+    }
+
+    pub fn heightfield<P: TerrainPrimitive + Sync>(
+        &self,
+        terrain: &P,
+        tile: TileId,
+    ) -> Heightfield {
+        let resolution = self.settings.resolution;
+        let data = sample_grid(
+            terrain,
+            &self.contour,
+            self.settings.sea_level,
+            self.settings.base_elevation,
+            resolution,
+            resolution,
+            |x, y| self.position(tile, x as i64, y as i64),
+        );
+
+        Heightfield::new(
+            resolution,
+            resolution,
+            self.cell_size(tile.lod),
+            self.position(tile, 0, 0),
+            data,
+        )
+    }
+
+    /// A mesh of the tile with skirts along its edges.
+    ///
+    /// The normals come from central differences over a ring of samples around the tile, so
+    /// they match along the edges shared with the neighbours.
+    pub fn mesh<P: TerrainPrimitive + Sync>(&self, terrain: &P, tile: TileId) -> Mesh {
+        let resolution = self.settings.resolution;
+        let cell_size = self.cell_size(tile.lod);
+
+        // samples with a border of one around the tile
+        let size = resolution + 2;
+        let heights = sample_grid(
+            terrain,
+            &self.contour,
+            self.settings.sea_level,
+            self.settings.base_elevation,
+            size,
+            size,
+            |x, y| self.position(tile, x as i64 - 1, y as i64 - 1),
+        );
+        let height =
+            |x: i64, y: i64| f64::from(heights[(y + 1) as usize * size + (x + 1) as usize]);
+
+        let mut positions = Vec::with_capacity(resolution * (resolution + 4));
+        let mut normals = Vec::with_capacity(resolution * (resolution + 4));
+        for y in 0..resolution as i64 {
+            for x in 0..resolution as i64 {
+                let pos = self.position(tile, x, y);
+                positions.push(Point3::new(pos.x, pos.y, height(x, y)));
+
+                let dx = height(x + 1, y) - height(x - 1, y);
+                let dy = height(x, y + 1) - height(x, y - 1);
+                normals.push(Vector3::new(-dx, -dy, 2.0 * cell_size).normalize());
+            }
+        }
+
+        let mut indices = grid_indices(resolution, resolution);
+
+        // the edge vertices counter-clockwise around the tile
+        let last = resolution - 1;
+        let ring = (0..last)
+            .chain((0..last).map(|y| y * resolution + last))
+            .chain((1..resolution).rev().map(|x| last * resolution + x))
+            .chain((1..resolution).rev().map(|y| y * resolution))
+            .collect::<Vec<_>>();
+
+        let skirt = positions.len();
+        for &idx in &ring {
+            let pos = positions[idx];
+            positions.push(Point3::new(pos.x, pos.y, pos.z - self.settings.skirt_depth));
+            normals.push(normals[idx]);
+        }
+
+        for k in 0..ring.len() {
+            let next = (k + 1) % ring.len();
+            let (a, b) = (ring[k] as u32, ring[next] as u32);
+            let (a_skirt, b_skirt) = ((skirt + k) as u32, (skirt + next) as u32);
+            indices.extend_from_slice(&[a, a_skirt, b, b, a_skirt, b_skirt]);
+        }
+
+        Mesh {
+            positions,
+            normals,
+            indices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use primitives::{MountainPrimitive, MountainShape};
+
+    fn terrain() -> MountainPrimitive {
+        MountainPrimitive {
+            peak: Point3::new(500.0, 500.0, 300.0),
+            radius: 800.0,
+            slope: 0.5,
+            shape: MountainShape::Ridged {
+                height: 50.0,
+                wavelength: 100.0,
+                seed: 7,
+            },
+        }
+    }
+
+    fn tiling() -> Tiling {
+        let contour = vec![
+            Point2::new(0.0, 0.0),
+            Point2::new(1000.0, 0.0),
+            Point2::new(1000.0, 800.0),
+            Point2::new(0.0, 800.0),
+        ];
+        let settings = TileSettings {
+            resolution: 9,
+            max_lod: 3,
+            lod_distance: 1.0,
+            skirt_depth: 10.0,
+            sea_level: -5.0,
+            base_elevation: 0.0,
+        };
+
+        Tiling::new(contour, settings)
+    }
+
+    #[test]
+    fn tile_ids() {
+        let tile = TileId { x: 3, y: 2, lod: 2 };
+        assert_eq!(tile.parent(), Some(TileId { x: 1, y: 1, lod: 1 }));
+        assert_eq!(TileId::root().parent(), None);
+        for child in &tile.children() {
+            assert_eq!(child.parent(), Some(tile));
+        }
+    }
+
+    #[test]
+    fn shared_edges() {
+        let tiling = tiling();
+        let terrain = terrain();
+
+        // neighbours at the same level
+        let left = tiling.heightfield(&terrain, TileId { x: 0, y: 1, lod: 1 });
+        let right = tiling.heightfield(&terrain, TileId { x: 1, y: 1, lod: 1 });
+        for y in 0..9 {
+            assert_eq!(left.get(8, y), right.get(0, y));
+        }
+
+        // every other sample of a child is a sample of the parent
+        let parent = tiling.heightfield(&terrain, TileId { x: 1, y: 1, lod: 1 });
+        let child = tiling.heightfield(&terrain, TileId { x: 3, y: 2, lod: 2 });
+        for y in 0..5 {
+            for x in 0..5 {
+                assert_eq!(parent.get(x + 4, y), child.get(x * 2, y * 2));
+            }
+        }
+
+        // outside the contour
+        let top = tiling.heightfield(&terrain, TileId { x: 0, y: 1, lod: 1 });
+        assert_eq!(top.get(0, 8), -5.0);
+
+        // the same tile twice
+        let again = tiling.heightfield(&terrain, TileId { x: 0, y: 1, lod: 1 });
+        assert_eq!(left.data, again.data);
+    }
+
+    #[test]
+    fn mesh_with_skirts() {
+        let tiling = tiling();
+        let terrain = terrain();
+
+        let mesh = tiling.mesh(&terrain, TileId { x: 1, y: 0, lod: 1 });
+        assert_eq!(mesh.positions.len(), 81 + 32);
+        assert_eq!(mesh.triangle_count(), 128 + 64);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+        assert_eq!(mesh.positions[81].z, mesh.positions[0].z - 10.0);
+
+        // normals along the shared edge match
+        let left = tiling.mesh(&terrain, TileId { x: 0, y: 0, lod: 1 });
+        for y in 0..9 {
+            assert_eq!(left.positions[y * 9 + 8], mesh.positions[y * 9]);
+            assert_eq!(left.normals[y * 9 + 8], mesh.normals[y * 9]);
+        }
+    }
+
+    #[test]
+    fn select_tiles() {
+        let tiling = tiling();
+        let tiles = tiling.select(Point2::new(10.0, 10.0));
+
+        // the tiles cover the root without overlapping
+        let area = tiles
+            .iter()
+            .map(|&tile| {
+                let bb = tiling.bounding_box(tile);
+                (bb.max.x - bb.min.x) * (bb.max.y - bb.min.y)
+            }).sum::<f64>();
+        assert!((area - 1000.0 * 1000.0).abs() < 1e-6);
+
+        assert!(tiles.contains(&TileId { x: 0, y: 0, lod: 3 }));
+        assert!(tiles.iter().all(|tile| tile.lod >= 1));
+        assert!(tiles.iter().any(|tile| tile.lod < 3));
+    }
+}